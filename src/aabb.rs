@@ -18,6 +18,9 @@ fn ffmax(a: f32, b: f32) -> f32 {
     }
 }
 
+// Flat shapes get a box at least this thick so `Aabb::hit` still sees them.
+pub const MIN_BOX_EXTENT: f32 = 1.0e-4;
+
 #[derive(Copy, Clone)]
 pub struct Aabb {
     pub min: Vec3,
@@ -36,14 +39,85 @@ impl Aabb {
         }
     }
 
+    // Widens every axis thinner than `min_extent` to that size, so flat
+    // shapes such as axis-aligned triangles still have a box rays can hit.
+    // Far from the origin the extent grows with the coordinates, as a fixed
+    // amount would be rounded away.
+    pub fn padded(&self, min_extent: f32) -> Self {
+        let mut min = [0.0; 3];
+        let mut max = [0.0; 3];
+        for axis in 0..3 {
+            let (low, high) = (*self.min.get(axis), *self.max.get(axis));
+            let magnitude = low.abs().max(high.abs());
+            let min_extent = min_extent.max(magnitude * f32::EPSILON * 4.0);
+            let grow = ((min_extent - (high - low)) * 0.5).max(0.0);
+            min[axis] = low - grow;
+            max[axis] = high + grow;
+        }
+        Self {
+            min: Vec3::from(min[0], min[1], min[2]),
+            max: Vec3::from(max[0], max[1], max[2]),
+        }
+    }
+
+    // The box of shapes without bounds, which `World` keeps out of the BVH.
+    pub fn unbounded() -> Self {
+        Self {
+            min: Vec3::from(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            max: Vec3::from(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        }
+    }
+
+    // False for the boxes of unbounded shapes such as infinite planes.
+    pub fn is_finite(&self) -> bool {
+        (0..3).all(|axis| self.min.get(axis).is_finite() && self.max.get(axis).is_finite())
+    }
+
+    // Used by the SAH BVH builder: the chance of a random ray hitting a box
+    // is proportional to its area.
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
     // Shamelessly stolen from GPSnoopy's implementation
     pub fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
-        let inv_d = ray.direction.invert_elems();
-        let t0 = (self.min - ray.origin).direct_product(&inv_d);
-        let t1 = (self.max - ray.origin).direct_product(&inv_d);
+        self.hit_inverse(&ray.origin, &ray.direction.invert_elems(), tmin, tmax)
+    }
+
+    // `hit` for a ray whose inverted direction is already known, as when
+    // testing it against many boxes in turn.
+    pub fn hit_inverse(&self, origin: &Vec3, inv_d: &Vec3, tmin: f32, tmax: f32) -> bool {
+        let t0 = (&self.min - origin).direct_product(inv_d);
+        let t1 = (&self.max - origin).direct_product(inv_d);
 
         let t_min = ffmax(tmin, t0.min(&t1).max_elem());
         let t_max = ffmin(tmax, t0.max(&t1).min_elem());
-        t_max > t_min
+        // Inclusive, as a ray crossing a box that is flat along its
+        // direction enters and leaves it at once.
+        t_max >= t_min
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_survives_rounding_far_from_the_origin() {
+        for &x in &[0.0, 1.0, 3000.0, -1.0e6] {
+            let flat = Aabb::build(Vec3::from(x, -1.0, -1.0), Vec3::from(x, 1.0, 1.0));
+            let padded = flat.padded(MIN_BOX_EXTENT);
+            // Up to rounding of the two sides.
+            assert!(
+                padded.max.x() - padded.min.x() >= 0.99 * MIN_BOX_EXTENT,
+                "{}",
+                x
+            );
+            assert!(padded.min.x() < &x && padded.max.x() > &x, "{}", x);
+        }
+        // Boxes that are thick enough already are left alone.
+        let thick = Aabb::build(Vec3::from(-2.0, 0.0, 0.0), Vec3::from(2.0, 1.0, 1.0));
+        assert_eq!(thick.padded(MIN_BOX_EXTENT).min.x(), &-2.0);
     }
 }
@@ -0,0 +1,844 @@
+// Bounding volume hierarchies over the objects of a scene.
+//
+// Two builders are available. The surface area heuristic (SAH) builder,
+// the default, sorts primitives into bins along each axis and splits where
+// the estimated cost of tracing a ray through the two halves is lowest. The
+// original builder splits at the median along a random axis; it is kept for
+// comparison.
+//
+// The SAH builder works on several threads: once a node is split, one half
+// is handed to another thread while the current one carries on with the
+// other, until each has a subtree to itself. The median builder draws its
+// axes from the scene's random stream, so it stays on one thread to keep
+// the draws in order.
+//
+// Either way the tree is stored flat: nodes in one array in depth-first
+// order and the primitives in another, ordered so that every leaf covers a
+// contiguous range of them. Traversal walks it with an explicit stack,
+// nearer child first, and skips boxes beyond the closest hit so far. By
+// default the binary tree is then collapsed into four-wide nodes, see
+// `qbvh`.
+
+use crate::aabb::Aabb;
+use crate::hitable::Hitable;
+use crate::packet::{self, RayPacket, PACKET_SIZE};
+use crate::qbvh::{self, WideNode};
+use crate::ray::*;
+use crate::rng::Random;
+use crate::vec3::*;
+
+use std::cmp::Ordering;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BvhBuilder {
+    Sah,
+    RandomMedian,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhSettings {
+    pub builder: BvhBuilder,
+    // The most primitives a SAH leaf may hold; the median builder always
+    // makes leaves of one.
+    pub max_leaf_size: usize,
+    // Threads the SAH builder may use. The tree comes out the same for any
+    // number.
+    pub thread_count: usize,
+    // Collapses the tree into nodes with four children, tested against a
+    // ray together with SSE.
+    pub wide: bool,
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        BvhSettings {
+            builder: BvhBuilder::Sah,
+            max_leaf_size: 4,
+            thread_count: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            wide: true,
+        }
+    }
+}
+
+// Smaller subtrees are not worth a thread of their own.
+const PARALLEL_BUILD_MIN: usize = 4096;
+
+// Deep enough for any tree the builders make, see `MAX_SAH_DEPTH`.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+// Boxes are kept as plain floats rather than `Vec3`s, which would pad each
+// corner to 16 bytes, so that two nodes fit in a cache line.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct LinearNode {
+    pub(crate) min: [f32; 3],
+    pub(crate) max: [f32; 3],
+    // For a leaf, the first of its primitives. For an interior node, the
+    // second child; the first child is always the next node.
+    pub(crate) offset: u32,
+    // The number of primitives in a leaf, 0 for interior nodes.
+    pub(crate) count: u16,
+    // The axis the children were split along.
+    axis: u8,
+}
+
+const _: () = assert!(std::mem::size_of::<LinearNode>() == 32);
+
+impl LinearNode {
+    pub(crate) fn bounds(&self) -> Aabb {
+        Aabb::build(
+            Vec3::from(self.min[0], self.min[1], self.min[2]),
+            Vec3::from(self.max[0], self.max[1], self.max[2]),
+        )
+    }
+}
+
+// The closest hit of a ray so far, and which primitive it came from.
+pub(crate) struct Closest<'a> {
+    pub(crate) record: Option<HitRecord<'a>>,
+    index: usize,
+    // The hit's distance, or the end of the ray's range before any hit.
+    pub(crate) t_max: f32,
+    // How far primitives are asked to look. Past a hit that includes its
+    // own distance, since a tie may still go to an earlier primitive.
+    pub(crate) limit: f32,
+}
+
+impl<'a> Closest<'a> {
+    pub(crate) fn new(t_max: f32) -> Self {
+        Closest {
+            record: None,
+            index: 0,
+            t_max,
+            limit: t_max,
+        }
+    }
+
+    // Keeps a hit found within `limit`.
+    pub(crate) fn offer(&mut self, record: HitRecord<'a>, index: usize) {
+        if record.t < self.t_max || index < self.index {
+            self.t_max = record.t;
+            self.limit = record.t.next_up();
+            self.index = index;
+            self.record = Some(record);
+        }
+    }
+
+    // Tests the primitives of a leaf, `first` being the index of the first.
+    pub(crate) fn hit_leaf(
+        &mut self,
+        leaf: &'a [Box<dyn Hitable>],
+        first: usize,
+        ray: &Ray,
+        t_min: f32,
+    ) {
+        for (offset, primitive) in leaf.iter().enumerate() {
+            if let Some(record) = primitive.hit(ray, t_min, self.limit) {
+                self.offer(record, first + offset);
+            }
+        }
+    }
+}
+
+enum Nodes {
+    Binary(Vec<LinearNode>),
+    Wide(Vec<WideNode>),
+}
+
+pub struct BvhTree {
+    nodes: Nodes,
+    primitives: Vec<Box<dyn Hitable>>,
+}
+
+// What the builders need to know about a primitive. These are reordered
+// while the primitives themselves stay where they are until the end.
+#[derive(Copy, Clone)]
+struct PrimitiveRef {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+struct Builder<'a> {
+    settings: BvhSettings,
+    // Only the median builder, which never leaves the first thread, draws
+    // from this.
+    rnd: Option<&'a mut Random>,
+    nodes: Vec<LinearNode>,
+    // Threads this subtree may still use, counting the current one.
+    threads: usize,
+}
+
+impl BvhTree {
+    // Builds with the default settings.
+    pub fn build(hitables: &mut Vec<Box<dyn Hitable>>, rnd: &mut Random) -> Self {
+        Self::build_with(hitables, rnd, &BvhSettings::default())
+    }
+
+    // Takes every object out of `hitables`. Only the median builder draws
+    // from `rnd`. An empty tree hits nothing.
+    pub fn build_with(
+        hitables: &mut Vec<Box<dyn Hitable>>,
+        rnd: &mut Random,
+        settings: &BvhSettings,
+    ) -> Self {
+        let threads = match settings.builder {
+            BvhBuilder::Sah => settings.thread_count.max(1),
+            BvhBuilder::RandomMedian => 1,
+        };
+        let mut refs = primitive_refs(hitables, threads);
+
+        let mut builder = Builder {
+            settings: BvhSettings {
+                max_leaf_size: settings.max_leaf_size.clamp(1, u16::MAX as usize),
+                ..*settings
+            },
+            rnd: Some(rnd),
+            nodes: Vec::with_capacity(refs.len() * 2),
+            threads,
+        };
+        if !refs.is_empty() {
+            builder.build_node(&mut refs, 0, 0);
+        }
+
+        let mut primitives: Vec<Option<Box<dyn Hitable>>> = hitables.drain(..).map(Some).collect();
+        BvhTree {
+            nodes: if settings.wide {
+                Nodes::Wide(qbvh::collapse(&builder.nodes))
+            } else {
+                Nodes::Binary(builder.nodes)
+            },
+            primitives: refs
+                .iter()
+                .map(|primitive| primitives[primitive.index].take().unwrap())
+                .collect(),
+        }
+    }
+}
+
+impl Builder<'_> {
+    // Appends the subtree over `refs`, which end up as the primitives from
+    // `first` on.
+    fn build_node(&mut self, refs: &mut [PrimitiveRef], first: usize, depth: usize) {
+        let bounds = refs[1..].iter().fold(refs[0].bounds, |bounds, primitive| {
+            Aabb::surrounding_box(&bounds, &primitive.bounds)
+        });
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            min: [*bounds.min.x(), *bounds.min.y(), *bounds.min.z()],
+            max: [*bounds.max.x(), *bounds.max.y(), *bounds.max.z()],
+            offset: first as u32,
+            count: 0,
+            axis: 0,
+        });
+
+        let split = match self.settings.builder {
+            BvhBuilder::Sah => sah::split(refs, &bounds, self.settings.max_leaf_size, depth),
+            BvhBuilder::RandomMedian => random_median_split(refs, self.rnd.as_deref_mut().unwrap()),
+        };
+        match split {
+            Some((axis, middle)) => {
+                let (left, right) = refs.split_at_mut(middle);
+                let second = if self.threads > 1 && right.len() >= PARALLEL_BUILD_MIN {
+                    self.build_in_parallel(left, right, first, middle, depth)
+                } else {
+                    self.build_node(left, first, depth + 1);
+                    let second = self.nodes.len() as u32;
+                    self.build_node(right, first + middle, depth + 1);
+                    second
+                };
+                let node = &mut self.nodes[index];
+                node.offset = second;
+                node.axis = axis as u8;
+            }
+            None => self.nodes[index].count = refs.len() as u16,
+        }
+    }
+
+    // Builds both children of a node, the second on a new thread with half
+    // of the threads left, and returns the index of the second.
+    fn build_in_parallel(
+        &mut self,
+        left: &mut [PrimitiveRef],
+        right: &mut [PrimitiveRef],
+        first: usize,
+        middle: usize,
+        depth: usize,
+    ) -> u32 {
+        let threads = self.threads;
+        let right_threads = threads / 2;
+        let settings = self.settings;
+        let right_nodes = std::thread::scope(|scope| {
+            let worker = scope.spawn(move || {
+                let mut builder = Builder {
+                    settings,
+                    rnd: None,
+                    nodes: Vec::with_capacity(right.len() * 2),
+                    threads: right_threads,
+                };
+                builder.build_node(right, first + middle, depth + 1);
+                builder.nodes
+            });
+            self.threads = threads - right_threads;
+            self.build_node(left, first, depth + 1);
+            worker.join().unwrap()
+        });
+        self.threads = threads;
+
+        // The second subtree numbered its nodes from 0; primitive offsets
+        // were absolute all along.
+        let second = self.nodes.len() as u32;
+        self.nodes.extend(right_nodes.into_iter().map(|mut node| {
+            if node.count == 0 {
+                node.offset += second;
+            }
+            node
+        }));
+        second
+    }
+}
+
+// Gathers the boxes of `hitables` in chunks on up to `threads` threads, as
+// finding them can take a while for large meshes.
+fn primitive_refs(hitables: &[Box<dyn Hitable>], threads: usize) -> Vec<PrimitiveRef> {
+    let chunk_size = hitables.len().div_ceil(threads).max(PARALLEL_BUILD_MIN);
+    let chunk_refs = |(chunk, hitables): (usize, &[Box<dyn Hitable>])| {
+        hitables
+            .iter()
+            .enumerate()
+            .map(|(offset, hitable)| {
+                let bounds = hitable.bounding_box();
+                PrimitiveRef {
+                    index: chunk * chunk_size + offset,
+                    bounds,
+                    centroid: &(bounds.min + bounds.max) * 0.5,
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    if hitables.len() <= chunk_size {
+        return chunk_refs((0, hitables));
+    }
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = hitables
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|chunk| scope.spawn(move || chunk_refs(chunk)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+// The original builder: sorts by box minimum along a random axis and splits
+// in the middle, down to single primitives.
+fn random_median_split(refs: &mut [PrimitiveRef], rnd: &mut Random) -> Option<(usize, usize)> {
+    match refs.len() {
+        1 => return None,
+        2 => return Some((0, 1)),
+        _ => {}
+    };
+
+    let axis = (rnd.gen() * 3.0) as usize;
+    refs.sort_by(|left, right| {
+        let bb_left = *left.bounds.min.get(axis);
+        let bb_right = *right.bounds.min.get(axis);
+        if bb_left < bb_right {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }
+    });
+    Some((axis, refs.len() / 2))
+}
+
+// Splits in half along the axis the centroids spread furthest on.
+fn centroid_median_split(refs: &mut [PrimitiveRef]) -> (usize, usize) {
+    let (min, max) = centroid_extent(refs);
+    let extent = max - min;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+    let middle = refs.len() / 2;
+    refs.select_nth_unstable_by(middle, |left, right| {
+        left.centroid.get(axis).total_cmp(right.centroid.get(axis))
+    });
+    (axis, middle)
+}
+
+fn centroid_extent(refs: &[PrimitiveRef]) -> (Vec3, Vec3) {
+    refs.iter().fold(
+        (refs[0].centroid, refs[0].centroid),
+        |(min, max), primitive| (min.min(&primitive.centroid), max.max(&primitive.centroid)),
+    )
+}
+
+mod sah {
+    use super::*;
+
+    // Candidate split positions per axis are the boundaries between bins.
+    const BINS: usize = 16;
+    // The cost of visiting a node, relative to intersecting one primitive.
+    const TRAVERSAL_COST: f32 = 0.125;
+    // SAH splits that peel off a few primitives at a time, as on long thin
+    // geometry, could nest deeper than the traversal stack allows. Past
+    // this depth nodes are split at the median instead, and halving adds
+    // at most 32 more levels.
+    const MAX_SAH_DEPTH: usize = MAX_DEPTH - 32;
+
+    // The axis and the number of primitives moved to the front for the
+    // first child, or None for a leaf.
+    pub(super) fn split(
+        refs: &mut [PrimitiveRef],
+        bounds: &Aabb,
+        max_leaf_size: usize,
+        depth: usize,
+    ) -> Option<(usize, usize)> {
+        if refs.len() == 1 {
+            return None;
+        }
+        if depth >= MAX_SAH_DEPTH {
+            return Some(centroid_median_split(refs));
+        }
+
+        let leaf_cost = refs.len() as f32;
+        match find_split(refs, bounds) {
+            Some((cost, axis, bin, centroid_bounds))
+                if refs.len() > max_leaf_size || cost < leaf_cost =>
+            {
+                let middle = partition(refs, |primitive| {
+                    bin_index(&primitive.centroid, axis, &centroid_bounds) <= bin
+                });
+                Some((axis, middle))
+            }
+            // Every centroid in the same place leaves nothing to choose
+            // between, but the leaf still has to be kept small.
+            None if refs.len() > max_leaf_size => Some(centroid_median_split(refs)),
+            _ => None,
+        }
+    }
+
+    fn bin_index(centroid: &Vec3, axis: usize, centroid_bounds: &Aabb) -> usize {
+        let low = *centroid_bounds.min.get(axis);
+        let extent = centroid_bounds.max.get(axis) - low;
+        let bin = ((centroid.get(axis) - low) / extent * BINS as f32) as usize;
+        bin.min(BINS - 1)
+    }
+
+    // The cheapest split as (cost, axis, last bin on the left, centroid
+    // bounds), in units of one primitive intersection. None when the
+    // centroids cannot be told apart along any axis.
+    fn find_split(refs: &[PrimitiveRef], bounds: &Aabb) -> Option<(f32, usize, usize, Aabb)> {
+        let (min, max) = centroid_extent(refs);
+        let centroid_bounds = Aabb::build(min, max);
+        let area = bounds.surface_area();
+        let inverse_area = if area > 0.0 && area.is_finite() {
+            1.0 / area
+        } else {
+            0.0
+        };
+
+        let mut best: Option<(f32, usize, usize, Aabb)> = None;
+        for axis in 0..3 {
+            let extent = centroid_bounds.max.get(axis) - centroid_bounds.min.get(axis);
+            if !(extent > 0.0 && extent.is_finite()) {
+                continue;
+            }
+
+            let mut counts = [0usize; BINS];
+            let mut bin_bounds: [Option<Aabb>; BINS] = [None; BINS];
+            for primitive in refs {
+                let bin = bin_index(&primitive.centroid, axis, &centroid_bounds);
+                counts[bin] += 1;
+                bin_bounds[bin] = Some(match bin_bounds[bin] {
+                    Some(bounds) => Aabb::surrounding_box(&bounds, &primitive.bounds),
+                    None => primitive.bounds,
+                });
+            }
+
+            // Area and count of everything right of each boundary, swept
+            // from the right, then matched with a sweep from the left.
+            let mut right_costs = [0.0f32; BINS];
+            let mut right_bounds: Option<Aabb> = None;
+            let mut right_count = 0;
+            for bin in (1..BINS).rev() {
+                right_bounds = merge(right_bounds, bin_bounds[bin]);
+                right_count += counts[bin];
+                right_costs[bin - 1] =
+                    right_bounds.map_or(0.0, |bounds| bounds.surface_area()) * right_count as f32;
+            }
+            let mut left_bounds: Option<Aabb> = None;
+            let mut left_count = 0;
+            for bin in 0..BINS - 1 {
+                left_bounds = merge(left_bounds, bin_bounds[bin]);
+                left_count += counts[bin];
+                if left_count == 0 || left_count == refs.len() {
+                    continue;
+                }
+                let left_cost =
+                    left_bounds.map_or(0.0, |bounds| bounds.surface_area()) * left_count as f32;
+                let cost = TRAVERSAL_COST + (left_cost + right_costs[bin]) * inverse_area;
+                if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                    best = Some((cost, axis, bin, centroid_bounds));
+                }
+            }
+        }
+        best
+    }
+
+    fn merge(bounds: Option<Aabb>, other: Option<Aabb>) -> Option<Aabb> {
+        match (bounds, other) {
+            (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, &b)),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    // Moves the items satisfying `left` to the front, returning how many
+    // there are.
+    fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], left: F) -> usize {
+        let mut middle = 0;
+        for index in 0..items.len() {
+            if left(&items[index]) {
+                items.swap(middle, index);
+                middle += 1;
+            }
+        }
+        middle
+    }
+}
+
+// A whole tree can itself be placed in a scene, e.g. a mesh shared by
+// several instances.
+impl Hitable for BvhTree {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        match &self.nodes {
+            Nodes::Binary(nodes) => hit_binary(nodes, &self.primitives, ray, t_min, t_max),
+            Nodes::Wide(nodes) => qbvh::hit(nodes, &self.primitives, ray, t_min, t_max),
+        }
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: i32,
+        t_min: f32,
+        t_max: &[f32; PACKET_SIZE],
+    ) -> [Option<HitRecord<'_>>; PACKET_SIZE] {
+        match &self.nodes {
+            // The binary tree, kept for comparison, has no packet traversal:
+            // each ray walks it alone.
+            Nodes::Binary(_) => packet::hit_each(self, packet, active, t_min, t_max),
+            Nodes::Wide(nodes) => {
+                qbvh::hit_packet(nodes, &self.primitives, packet, active, t_min, t_max)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let root = match &self.nodes {
+            Nodes::Binary(nodes) => nodes.first().map(LinearNode::bounds),
+            Nodes::Wide(nodes) => nodes.first().map(WideNode::bounds),
+        };
+        root.unwrap_or_else(|| Aabb::build(Vec3::default(), Vec3::default()))
+    }
+}
+
+fn hit_binary<'a>(
+    nodes: &[LinearNode],
+    primitives: &'a [Box<dyn Hitable>],
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    if nodes.is_empty() {
+        return None;
+    }
+    let inv_d = ray.direction.invert_elems();
+    let mut closest = Closest::new(t_max);
+
+    // Far children waiting until the near side is done.
+    let mut stack = [0u32; MAX_DEPTH];
+    let mut stack_size = 0;
+    let mut index = 0;
+    loop {
+        let node = &nodes[index];
+        if node
+            .bounds()
+            .hit_inverse(&ray.origin, &inv_d, t_min, closest.t_max)
+        {
+            if node.count > 0 {
+                let first = node.offset as usize;
+                let leaf = &primitives[first..first + node.count as usize];
+                closest.hit_leaf(leaf, first, ray, t_min);
+            } else {
+                // The second child holds what lies further along the
+                // split axis, so a ray going backwards starts there.
+                let (near, far) = if *inv_d.get(node.axis as usize) < 0.0 {
+                    (node.offset as usize, index + 1)
+                } else {
+                    (index + 1, node.offset as usize)
+                };
+                stack[stack_size] = far as u32;
+                stack_size += 1;
+                index = near;
+                continue;
+            }
+        }
+        if stack_size == 0 {
+            break;
+        }
+        stack_size -= 1;
+        index = stack[stack_size] as usize;
+    }
+    closest.record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Sphere;
+    use crate::material::Lambertian;
+    use crate::mesh::TriangleMesh;
+
+    // Overlapping spheres and triangles, some of them repeated so that rays
+    // find ties.
+    fn scene(rnd: &mut Random) -> Vec<Box<dyn Hitable>> {
+        scene_of_size(rnd, 200, 300)
+    }
+
+    fn scene_of_size(rnd: &mut Random, spheres: usize, triangles: u32) -> Vec<Box<dyn Hitable>> {
+        let mut point = |scale: f32| {
+            Vec3::from(
+                (rnd.gen() - 0.5) * scale,
+                (rnd.gen() - 0.5) * scale,
+                (rnd.gen() - 0.5) * scale,
+            )
+        };
+        let material = || Box::new(Lambertian::with_albedo(Vec3::from(0.5, 0.5, 0.5)));
+
+        let mut hitables: Vec<Box<dyn Hitable>> = Vec::new();
+        for _ in 0..spheres {
+            hitables.push(Box::new(Sphere {
+                center: point(20.0),
+                radius: 0.2 + point(1.0).x().abs(),
+                material: material(),
+            }));
+        }
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for triangle in 0..triangles {
+            let corner = point(20.0);
+            for _ in 0..3 {
+                positions.push(corner + point(3.0));
+            }
+            indices.push([3 * triangle, 3 * triangle + 1, 3 * triangle + 2]);
+        }
+        indices.extend_from_within(..50);
+        let mesh = TriangleMesh::new(positions, indices, material()).unwrap();
+        hitables.extend(mesh.into_triangles());
+        hitables
+    }
+
+    fn random_ray(rnd: &mut Random) -> Ray {
+        let mut point = |scale: f32| {
+            Vec3::from(
+                (rnd.gen() - 0.5) * scale,
+                (rnd.gen() - 0.5) * scale,
+                (rnd.gen() - 0.5) * scale,
+            )
+        };
+        let origin = point(40.0);
+        Ray {
+            origin,
+            direction: point(20.0) - origin,
+            ..Ray::default()
+        }
+    }
+
+    fn brute_force<'a>(
+        hitables: &'a [Box<dyn Hitable>],
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord<'a>> {
+        let mut closest: Option<HitRecord<'a>> = None;
+        for hitable in hitables {
+            let limit = closest.as_ref().map_or(t_max, |record| record.t);
+            if let Some(record) = hitable.hit(ray, t_min, limit) {
+                closest = Some(record);
+            }
+        }
+        closest
+    }
+
+    // Ties may go to either primitive, but then both are hit at the same
+    // point.
+    fn same_hit(a: &Option<HitRecord<'_>>, b: &Option<HitRecord<'_>>) -> bool {
+        let bits = |v: &Vec3| [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+        match (a, b) {
+            (Some(a), Some(b)) => a.t == b.t && bits(&a.p) == bits(&b.p),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    fn trees() -> Vec<BvhSettings> {
+        let mut settings = Vec::new();
+        for builder in [BvhBuilder::Sah, BvhBuilder::RandomMedian] {
+            for max_leaf_size in [1, 4] {
+                for wide in [false, true] {
+                    settings.push(BvhSettings {
+                        builder,
+                        max_leaf_size,
+                        thread_count: 2,
+                        wide,
+                    });
+                }
+            }
+        }
+        settings
+    }
+
+    #[test]
+    fn finds_the_same_closest_hit_as_a_brute_force_scan() {
+        let reference = scene(&mut Random::create_with_seed(1));
+        for settings in trees() {
+            let mut hitables = scene(&mut Random::create_with_seed(1));
+            let tree =
+                BvhTree::build_with(&mut hitables, &mut Random::create_with_seed(2), &settings);
+
+            let mut rnd = Random::create_with_seed(3);
+            let mut hits = 0;
+            for _ in 0..2000 {
+                let ray = random_ray(&mut rnd);
+                // Some rays end early, inside the scene.
+                let t_max = if rnd.gen() < 0.25 {
+                    rnd.gen()
+                } else {
+                    f32::MAX
+                };
+                let expected = brute_force(&reference, &ray, 0.001, t_max);
+                let found = tree.hit(&ray, 0.001, t_max);
+                assert!(same_hit(&found, &expected), "{:?}", settings);
+                hits += found.is_some() as usize;
+            }
+            assert!(hits > 500, "only {} rays hit anything", hits);
+        }
+    }
+
+    #[test]
+    fn threads_do_not_change_the_tree() {
+        // Enough primitives for several chunks of boxes and for subtrees
+        // built on threads of their own.
+        let reference = scene_of_size(&mut Random::create_with_seed(5), 1000, 8000);
+        assert!(reference.len() > 2 * PARALLEL_BUILD_MIN);
+        let build = |thread_count| {
+            let mut hitables = scene_of_size(&mut Random::create_with_seed(5), 1000, 8000);
+            let settings = BvhSettings {
+                thread_count,
+                wide: false,
+                ..BvhSettings::default()
+            };
+            BvhTree::build_with(&mut hitables, &mut Random::create_with_seed(2), &settings)
+        };
+        let node_bits = |tree: &BvhTree| match &tree.nodes {
+            Nodes::Binary(nodes) => nodes
+                .iter()
+                .map(|node| {
+                    (
+                        node.min.map(f32::to_bits),
+                        node.max.map(f32::to_bits),
+                        node.offset,
+                        node.count,
+                        node.axis,
+                    )
+                })
+                .collect::<Vec<_>>(),
+            Nodes::Wide(_) => unreachable!(),
+        };
+        let primitive_bits = |tree: &BvhTree| {
+            tree.primitives
+                .iter()
+                .map(|primitive| {
+                    let bounds = primitive.bounding_box();
+                    (0..3)
+                        .map(|axis| {
+                            (
+                                bounds.min.get(axis).to_bits(),
+                                bounds.max.get(axis).to_bits(),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let single = build(1);
+        let parallel = build(4);
+        assert!(node_bits(&single) == node_bits(&parallel));
+        assert!(primitive_bits(&single) == primitive_bits(&parallel));
+
+        let mut rnd = Random::create_with_seed(3);
+        for _ in 0..200 {
+            let ray = random_ray(&mut rnd);
+            let expected = brute_force(&reference, &ray, 0.001, f32::MAX);
+            assert!(same_hit(&single.hit(&ray, 0.001, f32::MAX), &expected));
+            assert!(same_hit(&parallel.hit(&ray, 0.001, f32::MAX), &expected));
+        }
+    }
+
+    #[test]
+    fn packets_find_the_same_closest_hits() {
+        let reference = scene(&mut Random::create_with_seed(1));
+        for settings in trees() {
+            let mut hitables = scene(&mut Random::create_with_seed(1));
+            let tree =
+                BvhTree::build_with(&mut hitables, &mut Random::create_with_seed(2), &settings);
+
+            let mut rnd = Random::create_with_seed(4);
+            for round in 0..500 {
+                // Neighbouring rays, as the camera sends them, and unrelated
+                // ones; some lanes are left out.
+                let first = random_ray(&mut rnd);
+                let rays = std::array::from_fn(|lane| {
+                    if round % 2 == 0 {
+                        Ray {
+                            origin: first.origin,
+                            direction: first.direction + Vec3::from(0.01 * lane as f32, 0.0, 0.0),
+                            ..Ray::default()
+                        }
+                    } else {
+                        random_ray(&mut rnd)
+                    }
+                });
+                let packet = RayPacket::new(rays);
+                let t_max = [f32::MAX, 0.5, f32::MAX, rnd.gen()];
+                let active = if round % 3 == 0 { 0b1011 } else { 0b1111 };
+
+                let found = tree.hit_packet(&packet, active, 0.001, &t_max);
+                for lane in 0..PACKET_SIZE {
+                    let expected = if active & (1 << lane) != 0 {
+                        brute_force(&reference, &packet.rays[lane], 0.001, t_max[lane])
+                    } else {
+                        None
+                    };
+                    assert!(
+                        same_hit(&found[lane], &expected),
+                        "{:?} lane {}",
+                        settings,
+                        lane
+                    );
+                }
+            }
+        }
+    }
+}
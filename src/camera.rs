@@ -4,6 +4,52 @@ use crate::vec3::*;
 
 use std::f32;
 
+// User-facing camera description, turned into a `Camera` once the aspect
+// ratio of the image is known.
+#[derive(Copy, Clone)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub vertical_fov_degrees: f32,
+    pub aperture: f32,
+    pub focus_distance: f32,
+    // Rays are spread over [shutter_open, shutter_close]; equal times give a
+    // still image.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            look_from: Vec3::from(13.0, 2.0, 3.0),
+            look_at: Vec3::from(0.0, 0.0, 0.0),
+            up: Vec3::from(0.0, 1.0, 0.0),
+            vertical_fov_degrees: 20.0,
+            aperture: 0.1,
+            focus_distance: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        Camera::build(
+            &self.look_from,
+            &self.look_at,
+            &self.up,
+            self.vertical_fov_degrees,
+            aspect_ratio,
+            self.aperture,
+            self.focus_distance,
+        )
+        .with_shutter(self.shutter_open, self.shutter_close)
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Camera {
     pub origin: Vec3,
@@ -13,6 +59,8 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     lens_radius: f32,
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
@@ -47,17 +95,34 @@ impl Camera {
             u,
             v,
             lens_radius,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
     pub fn get_ray(&self, s: f32, t: f32, rnd: &mut Random) -> Ray {
         let rd = &random_in_unit_disk(rnd) * self.lens_radius;
         let offset = &self.u * *rd.x() + &self.v * *rd.y();
+        // A closed shutter draws no time sample, so still images keep the
+        // same random sequence as before motion blur existed.
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + rnd.gen() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
         Ray {
             origin: self.origin + offset,
             direction: self.lower_left_corner + &self.horizontal * s + &self.vertical * t
                 - self.origin
                 - offset,
+            time,
+            medium_sample: rnd.gen_separate(),
         }
     }
 }
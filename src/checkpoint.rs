@@ -0,0 +1,308 @@
+// Progressive render state and its on-disk checkpoint format.
+//
+// All values are little-endian:
+//     magic "RTCKPT", version u16, width u32, height u32, seed u64,
+//     scene hash u64, passes completed u32,
+//     per pixel: radiance sum as three f32 followed by the sample count u32,
+//     CRC-32 of everything before it.
+
+use crate::deflate::Crc32;
+use crate::vec3::Vec3;
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"RTCKPT";
+const VERSION: u16 = 1;
+
+pub struct Checkpoint {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    // Covers the scene and every setting that changes the rendered samples.
+    pub scene_hash: u64,
+    pub passes_completed: u32,
+    pub sums: Vec<Vec3>,
+    pub sample_counts: Vec<u32>,
+}
+
+impl Checkpoint {
+    pub fn new(width: usize, height: usize, seed: u64, scene_hash: u64) -> Self {
+        Checkpoint {
+            width,
+            height,
+            seed,
+            scene_hash,
+            passes_completed: 0,
+            sums: vec![Vec3::default(); width * height],
+            sample_counts: vec![0; width * height],
+        }
+    }
+
+    // Mean radiance per pixel; pixels without samples stay black.
+    pub fn average(&self) -> Vec<Vec3> {
+        self.sums
+            .iter()
+            .zip(self.sample_counts.iter())
+            .map(|(sum, count)| {
+                if *count == 0 {
+                    Vec3::default()
+                } else {
+                    sum / *count as f32
+                }
+            })
+            .collect()
+    }
+
+    // Writes to a temporary file first so an interrupted save never
+    // clobbers the previous checkpoint.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        let mut data = Vec::with_capacity(40 + self.sums.len() * 16);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&(self.width as u32).to_le_bytes());
+        data.extend_from_slice(&(self.height as u32).to_le_bytes());
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        data.extend_from_slice(&self.scene_hash.to_le_bytes());
+        data.extend_from_slice(&self.passes_completed.to_le_bytes());
+        for (sum, count) in self.sums.iter().zip(self.sample_counts.iter()) {
+            data.extend_from_slice(&sum.r().to_le_bytes());
+            data.extend_from_slice(&sum.g().to_le_bytes());
+            data.extend_from_slice(&sum.b().to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+        }
+        let mut crc = Crc32::new();
+        crc.update(&data);
+        data.extend_from_slice(&crc.finish().to_le_bytes());
+
+        {
+            let mut out = BufWriter::new(File::create(&temp_path)?);
+            out.write_all(&data)?;
+            out.flush()?;
+            out.get_ref().sync_all()?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut data = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+
+        if data.len() < 4 {
+            return Err(invalid("file is truncated"));
+        }
+        let (body, trailer) = data.split_at(data.len() - 4);
+        let mut crc = Crc32::new();
+        crc.update(body);
+        if crc.finish().to_le_bytes() != trailer {
+            return Err(invalid("checksum mismatch, the file is damaged"));
+        }
+
+        let mut reader = ByteReader { data: body };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+
+        let width = u32::from_le_bytes(reader.array()?) as usize;
+        let height = u32::from_le_bytes(reader.array()?) as usize;
+        let seed = u64::from_le_bytes(reader.array()?);
+        let scene_hash = u64::from_le_bytes(reader.array()?);
+        let passes_completed = u32::from_le_bytes(reader.array()?);
+
+        // The size comes from the file, so a damaged one must not overflow.
+        let pixel_count = width
+            .checked_mul(height)
+            .filter(|count| count.checked_mul(16) == Some(reader.data.len()));
+        let pixel_count = match pixel_count {
+            Some(count) => count,
+            None => return Err(invalid("pixel data does not match the image size")),
+        };
+        let mut sums = Vec::with_capacity(pixel_count);
+        let mut sample_counts = Vec::with_capacity(pixel_count);
+        for _ in 0..pixel_count {
+            let r = f32::from_le_bytes(reader.array()?);
+            let g = f32::from_le_bytes(reader.array()?);
+            let b = f32::from_le_bytes(reader.array()?);
+            sums.push(Vec3::from(r, g, b));
+            sample_counts.push(u32::from_le_bytes(reader.array()?));
+        }
+
+        Ok(Checkpoint {
+            width,
+            height,
+            seed,
+            scene_hash,
+            passes_completed,
+            sums,
+            sample_counts,
+        })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(invalid("file is truncated"));
+        }
+        let (head, tail) = self.data.split_at(count);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self.take(N)?;
+        Ok(bytes.try_into().unwrap_or([0; N]))
+    }
+}
+
+// 64-bit FNV-1a, used to recognise the scene a checkpoint belongs to. Unlike
+// std's DefaultHasher its output is stable between builds.
+pub struct SceneHasher {
+    hash: u64,
+}
+
+impl Default for SceneHasher {
+    fn default() -> Self {
+        SceneHasher {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl SceneHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= u64::from(*byte);
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}.ckpt", name, std::process::id()))
+    }
+
+    fn sample_checkpoint() -> Checkpoint {
+        let mut checkpoint = Checkpoint::new(3, 2, 42, 0x0123_4567_89ab_cdef);
+        checkpoint.passes_completed = 5;
+        for (i, (sum, count)) in checkpoint
+            .sums
+            .iter_mut()
+            .zip(checkpoint.sample_counts.iter_mut())
+            .enumerate()
+        {
+            *sum = Vec3::from(i as f32, 0.5 * i as f32, -1.0e-3 * i as f32);
+            *count = 7 * i as u32;
+        }
+        checkpoint
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let path = temp_path("round-trip");
+        let checkpoint = sample_checkpoint();
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.width, 3);
+        assert_eq!(loaded.height, 2);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.scene_hash, 0x0123_4567_89ab_cdef);
+        assert_eq!(loaded.passes_completed, 5);
+        assert_eq!(loaded.sample_counts, checkpoint.sample_counts);
+        for (a, b) in loaded.sums.iter().zip(checkpoint.sums.iter()) {
+            assert_eq!((a.r(), a.g(), a.b()), (b.r(), b.g(), b.b()));
+        }
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let path = temp_path("damaged");
+        sample_checkpoint().save(&path).unwrap();
+        let data = fs::read(&path).unwrap();
+
+        // A flipped bit anywhere, pixel data or trailer, fails the CRC.
+        for &offset in &[0, 20, data.len() / 2, data.len() - 1] {
+            let mut damaged = data.clone();
+            damaged[offset] ^= 0x10;
+            fs::write(&path, &damaged).unwrap();
+            let err = Checkpoint::load(&path).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("checksum"), "{}", err);
+        }
+
+        fs::write(&path, &data[..data.len() - 8]).unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        fs::write(&path, &data[..2]).unwrap();
+        let err = Checkpoint::load(&path).err().unwrap();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_valid_checksum_over_the_wrong_size() {
+        // Pixel data for a different image size, with a correct CRC.
+        let path = temp_path("wrong-size");
+        let mut checkpoint = sample_checkpoint();
+        checkpoint.width = 4;
+        checkpoint.save(&path).unwrap();
+        let err = Checkpoint::load(&path).err().unwrap();
+        assert!(err.to_string().contains("image size"), "{}", err);
+
+        // A size whose pixel data would not fit in memory.
+        checkpoint.width = u32::MAX as usize;
+        checkpoint.height = u32::MAX as usize;
+        checkpoint.save(&path).unwrap();
+        let err = Checkpoint::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("image size"), "{}", err);
+    }
+
+    #[test]
+    fn scene_hash_is_stable() {
+        let mut hasher = SceneHasher::new();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,432 @@
+use raytracer::exr;
+use raytracer::tiles::TileOrder;
+use raytracer::{
+    BitDepth, BvhBuilder, BvhSettings, CameraSettings, ImageFormat, OutputOptions, RenderSettings,
+    Vec3,
+};
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS]
+
+Render settings:
+  -w, --width <PIXELS>          Image width (default 3840)
+  -h, --height <PIXELS>         Image height (default 2160)
+  -s, --samples <COUNT>         Samples per pixel (default 1024)
+  -d, --max-depth <COUNT>       Maximum number of bounces per ray (default 20)
+  -t, --threads <COUNT>         Worker threads for rendering and building the BVH
+                                (default: available cores)
+      --tile-size <PIXELS>      Edge length of the square tiles handed to workers (default 32)
+      --tile-order <ORDER>      Order tiles are rendered in: scanline, spiral or hilbert
+                                (default spiral)
+      --seed <SEED>             Seed for scene generation and sampling (default 42)
+      --pass-samples <COUNT>    Samples per pixel added in each progressive pass (default 16)
+      --bvh <BUILDER>           How the BVH is built: sah (surface area heuristic) or
+                                median (random axis, median split) (default sah)
+      --bvh-leaf-size <COUNT>   Most objects in one BVH leaf with --bvh sah (default 4)
+      --bvh-width <CHILDREN>    Children per BVH node: 2, or 4 to test them together
+                                with SSE (default 4)
+  -o, --output <PATH>           Output file, '-' for stdout (default stdout)
+      --format <FORMAT>         Image format: png, ppm (binary P6), ppm-ascii (P3),
+                                hdr (Radiance RGBE) or exr (OpenEXR).
+                                Inferred from the output extension when omitted;
+                                stdout defaults to ppm-ascii
+      --bit-depth <BITS>        Bits per channel for png and ppm: 8 or 16 (default 8)
+      --exr-pixel <TYPE>        EXR channel type: half or float (default half)
+      --exr-compression <TYPE>  EXR compression: none or zip (default zip)
+      --scene <NAME|FILE>       Scene to render: random, or a scene description file
+                                (default random)
+      --progress <WHEN>         Show a progress bar on stderr: auto (when stderr is a
+                                terminal), always or never (default auto)
+
+Checkpoints:
+      --checkpoint <PATH>       Save the accumulated render to this file as it progresses
+      --checkpoint-interval <SECONDS>
+                                Minimum time between checkpoint saves (default 60)
+      --resume                  Continue from the checkpoint file if it exists
+
+Camera overrides:
+      --look-from <X,Y,Z>       Camera position (default 13,2,3)
+      --look-at <X,Y,Z>         Point the camera looks at (default 0,0,0)
+      --up <X,Y,Z>              Camera up vector (default 0,1,0)
+      --vfov <DEGREES>          Vertical field of view (default 20)
+      --aperture <SIZE>         Lens aperture, 0 for a pinhole (default 0.1)
+      --focus-distance <DIST>   Distance to the focal plane (default 10)
+      --shutter-open <TIME>     Time the shutter opens (default 0)
+      --shutter-close <TIME>    Time the shutter closes; later than --shutter-open
+                                for motion blur (default 0)
+
+      --help                    Print this message
+";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProgressMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneKind {
+    Random,
+    File(String),
+}
+
+pub struct Options {
+    pub render: RenderSettings,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: f32,
+    pub resume: bool,
+    pub output: Option<String>,
+    pub image: OutputOptions,
+    pub scene: SceneKind,
+    pub camera: CameraSettings,
+    pub progress: ProgressMode,
+    pub bvh: BvhSettings,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct ArgError {
+    message: String,
+}
+
+impl ArgError {
+    fn new(message: String) -> Self {
+        ArgError { message }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Options {
+    pub fn default() -> Self {
+        Options {
+            render: RenderSettings::default(),
+            checkpoint: None,
+            checkpoint_interval: 60.0,
+            resume: false,
+            output: None,
+            image: OutputOptions::for_format(ImageFormat::PpmAscii),
+            scene: SceneKind::Random,
+            camera: CameraSettings::default(),
+            progress: ProgressMode::Auto,
+            bvh: BvhSettings::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ArgError> {
+        let render = &self.render;
+        if render.width == 0 || render.height == 0 {
+            return Err(ArgError::new(format!(
+                "image size must be at least 1x1, got {}x{}",
+                render.width, render.height
+            )));
+        }
+        if render.samples_per_pixel == 0 {
+            return Err(ArgError::new("--samples must be at least 1".to_string()));
+        }
+        if render.max_depth < 1 {
+            return Err(ArgError::new("--max-depth must be at least 1".to_string()));
+        }
+        if render.thread_count == 0 {
+            return Err(ArgError::new("--threads must be at least 1".to_string()));
+        }
+        if render.pass_samples == 0 {
+            return Err(ArgError::new(
+                "--pass-samples must be at least 1".to_string(),
+            ));
+        }
+        if self.checkpoint_interval.is_nan() || self.checkpoint_interval < 0.0 {
+            return Err(ArgError::new(format!(
+                "--checkpoint-interval must not be negative, got {}",
+                self.checkpoint_interval
+            )));
+        }
+        if self.resume && self.checkpoint.is_none() {
+            return Err(ArgError::new(
+                "--resume needs a --checkpoint file to resume from".to_string(),
+            ));
+        }
+        if render.tile_size == 0 {
+            return Err(ArgError::new("--tile-size must be at least 1".to_string()));
+        }
+        if self.bvh.max_leaf_size == 0 {
+            return Err(ArgError::new(
+                "--bvh-leaf-size must be at least 1".to_string(),
+            ));
+        }
+
+        let camera = &self.camera;
+        let scalars = [
+            camera.vertical_fov_degrees,
+            camera.aperture,
+            camera.focus_distance,
+            camera.shutter_open,
+            camera.shutter_close,
+        ];
+        if scalars.iter().any(|value| !value.is_finite()) {
+            return Err(ArgError::new(
+                "camera parameters must be finite numbers".to_string(),
+            ));
+        }
+        if camera.vertical_fov_degrees <= 0.0 || camera.vertical_fov_degrees >= 180.0 {
+            return Err(ArgError::new(format!(
+                "--vfov must be between 0 and 180 degrees (exclusive), got {}",
+                camera.vertical_fov_degrees
+            )));
+        }
+        if camera.aperture < 0.0 {
+            return Err(ArgError::new(format!(
+                "--aperture must not be negative, got {}",
+                camera.aperture
+            )));
+        }
+        if camera.focus_distance <= 0.0 {
+            return Err(ArgError::new(format!(
+                "--focus-distance must be positive, got {}",
+                camera.focus_distance
+            )));
+        }
+        if camera.shutter_close < camera.shutter_open {
+            return Err(ArgError::new(format!(
+                "--shutter-close must not be before --shutter-open, got {} and {}",
+                camera.shutter_close, camera.shutter_open
+            )));
+        }
+        if (camera.look_from - camera.look_at).square_length() == 0.0 {
+            return Err(ArgError::new(
+                "--look-from and --look-at must be different points".to_string(),
+            ));
+        }
+        if camera.up.square_length() == 0.0 {
+            return Err(ArgError::new("--up must not be a zero vector".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+// Applies the arguments on top of `defaults`. Validation is left to the
+// caller so that scene file settings can be merged in first.
+pub fn parse_args<I>(args: I, defaults: Options) -> Result<Command, ArgError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = defaults;
+    let mut format = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Accept both "--name value" and "--name=value".
+        let (name, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => {
+                (arg[..index].to_string(), Some(arg[index + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        if name == "--help" {
+            return Ok(Command::Help);
+        }
+        if name == "--resume" {
+            options.resume = true;
+            continue;
+        }
+
+        let mut value = || -> Result<String, ArgError> {
+            match &inline_value {
+                Some(value) => Ok(value.clone()),
+                None => args
+                    .next()
+                    .ok_or_else(|| ArgError::new(format!("missing value for {}", name))),
+            }
+        };
+
+        match name.as_str() {
+            "-w" | "--width" => options.render.width = parse_value(&name, &value()?)?,
+            "-h" | "--height" => options.render.height = parse_value(&name, &value()?)?,
+            "-s" | "--samples" => options.render.samples_per_pixel = parse_value(&name, &value()?)?,
+            "-d" | "--max-depth" => options.render.max_depth = parse_value(&name, &value()?)?,
+            "-t" | "--threads" => options.render.thread_count = parse_value(&name, &value()?)?,
+            "--tile-size" => options.render.tile_size = parse_value(&name, &value()?)?,
+            "--tile-order" => options.render.tile_order = parse_tile_order(&value()?)?,
+            "--seed" => options.render.seed = parse_value(&name, &value()?)?,
+            "--pass-samples" => options.render.pass_samples = parse_value(&name, &value()?)?,
+            "--bvh" => options.bvh.builder = parse_bvh_builder(&value()?)?,
+            "--bvh-leaf-size" => options.bvh.max_leaf_size = parse_value(&name, &value()?)?,
+            "--bvh-width" => options.bvh.wide = parse_bvh_width(&value()?)?,
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--checkpoint-interval" => options.checkpoint_interval = parse_value(&name, &value()?)?,
+            "-o" | "--output" => {
+                let path = value()?;
+                options.output = if path == "-" { None } else { Some(path) };
+            }
+            "--format" => format = Some(parse_format(&value()?)?),
+            "--bit-depth" => options.image.bit_depth = parse_bit_depth(&value()?)?,
+            "--exr-pixel" => options.image.exr_pixel_type = parse_exr_pixel(&value()?)?,
+            "--exr-compression" => {
+                options.image.exr_compression = parse_exr_compression(&value()?)?
+            }
+            "--scene" => options.scene = parse_scene(&value()?)?,
+            "--progress" => options.progress = parse_progress(&value()?)?,
+            "--look-from" => options.camera.look_from = parse_vec3(&name, &value()?)?,
+            "--look-at" => options.camera.look_at = parse_vec3(&name, &value()?)?,
+            "--up" => options.camera.up = parse_vec3(&name, &value()?)?,
+            "--vfov" => options.camera.vertical_fov_degrees = parse_value(&name, &value()?)?,
+            "--aperture" => options.camera.aperture = parse_value(&name, &value()?)?,
+            "--focus-distance" => options.camera.focus_distance = parse_value(&name, &value()?)?,
+            "--shutter-open" => options.camera.shutter_open = parse_value(&name, &value()?)?,
+            "--shutter-close" => options.camera.shutter_close = parse_value(&name, &value()?)?,
+            _ => return Err(ArgError::new(format!("unknown option '{}'", arg))),
+        }
+    }
+
+    options.image.format = match (format, &options.output) {
+        (Some(format), _) => format,
+        (None, None) => ImageFormat::PpmAscii,
+        (None, Some(path)) => ImageFormat::from_path(Path::new(path)).ok_or_else(|| {
+            ArgError::new(format!(
+                "cannot infer an image format from '{}', use a .png, .ppm, .hdr or .exr extension or pass --format",
+                path
+            ))
+        })?,
+    };
+
+    Ok(Command::Render(Box::new(options)))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, ArgError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ArgError::new(format!("invalid value '{}' for {}", value, name)))
+}
+
+fn parse_vec3(name: &str, value: &str) -> Result<Vec3, ArgError> {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != 3 {
+        return Err(ArgError::new(format!(
+            "{} expects three comma separated numbers (X,Y,Z), got '{}'",
+            name, value
+        )));
+    }
+
+    Ok(Vec3::from(
+        parse_value(name, parts[0])?,
+        parse_value(name, parts[1])?,
+        parse_value(name, parts[2])?,
+    ))
+}
+
+fn parse_scene(value: &str) -> Result<SceneKind, ArgError> {
+    match value {
+        "random" => Ok(SceneKind::Random),
+        "" => Err(ArgError::new("--scene must not be empty".to_string())),
+        path => Ok(SceneKind::File(path.to_string())),
+    }
+}
+
+fn parse_progress(value: &str) -> Result<ProgressMode, ArgError> {
+    match value {
+        "auto" => Ok(ProgressMode::Auto),
+        "always" => Ok(ProgressMode::Always),
+        "never" => Ok(ProgressMode::Never),
+        _ => Err(ArgError::new(format!(
+            "unknown progress mode '{}', expected auto, always or never",
+            value
+        ))),
+    }
+}
+
+fn parse_tile_order(value: &str) -> Result<TileOrder, ArgError> {
+    match value {
+        "scanline" => Ok(TileOrder::Scanline),
+        "spiral" => Ok(TileOrder::Spiral),
+        "hilbert" => Ok(TileOrder::Hilbert),
+        _ => Err(ArgError::new(format!(
+            "unknown tile order '{}', expected scanline, spiral or hilbert",
+            value
+        ))),
+    }
+}
+
+fn parse_bvh_builder(value: &str) -> Result<BvhBuilder, ArgError> {
+    match value {
+        "sah" => Ok(BvhBuilder::Sah),
+        "median" => Ok(BvhBuilder::RandomMedian),
+        _ => Err(ArgError::new(format!(
+            "unknown BVH builder '{}', expected sah or median",
+            value
+        ))),
+    }
+}
+
+fn parse_bvh_width(value: &str) -> Result<bool, ArgError> {
+    match value {
+        "2" => Ok(false),
+        "4" => Ok(true),
+        _ => Err(ArgError::new(format!(
+            "unsupported BVH width '{}', expected 2 or 4",
+            value
+        ))),
+    }
+}
+
+fn parse_format(value: &str) -> Result<ImageFormat, ArgError> {
+    match value {
+        "png" => Ok(ImageFormat::Png),
+        "ppm" => Ok(ImageFormat::PpmBinary),
+        "ppm-ascii" => Ok(ImageFormat::PpmAscii),
+        "hdr" => Ok(ImageFormat::Hdr),
+        "exr" => Ok(ImageFormat::Exr),
+        _ => Err(ArgError::new(format!(
+            "unknown format '{}', expected one of: png, ppm, ppm-ascii, hdr, exr",
+            value
+        ))),
+    }
+}
+
+fn parse_bit_depth(value: &str) -> Result<BitDepth, ArgError> {
+    match value {
+        "8" => Ok(BitDepth::Eight),
+        "16" => Ok(BitDepth::Sixteen),
+        _ => Err(ArgError::new(format!(
+            "unsupported bit depth '{}', expected 8 or 16",
+            value
+        ))),
+    }
+}
+
+fn parse_exr_pixel(value: &str) -> Result<exr::PixelType, ArgError> {
+    match value {
+        "half" => Ok(exr::PixelType::Half),
+        "float" => Ok(exr::PixelType::Float),
+        _ => Err(ArgError::new(format!(
+            "unknown EXR pixel type '{}', expected half or float",
+            value
+        ))),
+    }
+}
+
+fn parse_exr_compression(value: &str) -> Result<exr::Compression, ArgError> {
+    match value {
+        "none" => Ok(exr::Compression::None),
+        "zip" => Ok(exr::Compression::Zip),
+        _ => Err(ArgError::new(format!(
+            "unknown EXR compression '{}', expected none or zip",
+            value
+        ))),
+    }
+}
@@ -0,0 +1,130 @@
+// Constructive solid geometry: boolean combinations of closed shapes.
+//
+// Each operand's surface crossings along the ray are walked in order,
+// tracking whether the ray is inside each operand; the result has a surface
+// wherever being inside the combination changes. Crossings are told apart
+// by their outward normals, so operands must be closed with normals facing
+// out, which every closed shape here has.
+
+use crate::aabb::Aabb;
+use crate::hitable::Hitable;
+use crate::ray::*;
+use crate::vec3::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // The first operand with the second carved out of it.
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+pub struct Csg {
+    operation: CsgOperation,
+    a: Box<dyn Hitable>,
+    b: Box<dyn Hitable>,
+    bounding_box: Aabb,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, a: Box<dyn Hitable>, b: Box<dyn Hitable>) -> Self {
+        let (box_a, box_b) = (a.bounding_box(), b.bounding_box());
+        let bounding_box = match operation {
+            CsgOperation::Union => Aabb::surrounding_box(&box_a, &box_b),
+            CsgOperation::Intersection => {
+                let min = box_a.min.max(&box_b.min);
+                // Disjoint operands leave an empty box, kept at a single
+                // point so that it stays well formed.
+                Aabb::build(min, box_a.max.min(&box_b.max).max(&min))
+            }
+            CsgOperation::Difference => box_a,
+        };
+        Csg {
+            operation,
+            a,
+            b,
+            bounding_box,
+        }
+    }
+
+    // The crossings of the combined surface after t_min, nearest first.
+    fn crossings(&self, ray: &Ray, t_min: f32) -> Vec<HitRecord<'_>> {
+        // Whether the ray starts inside an operand depends on crossings
+        // beyond any t_max, so both are followed all the way.
+        let mut hits_a = Vec::new();
+        let mut hits_b = Vec::new();
+        self.a.hit_all(ray, t_min, f32::INFINITY, &mut hits_a);
+        self.b.hit_all(ray, t_min, f32::INFINITY, &mut hits_b);
+
+        let entering = |record: &HitRecord| dot(&ray.direction, &record.normal) < 0.0;
+        // Starting inside shows as leaving first.
+        let mut in_a = hits_a.first().is_some_and(|record| !entering(record));
+        let mut in_b = hits_b.first().is_some_and(|record| !entering(record));
+
+        let mut crossings = Vec::new();
+        let mut hits_a = hits_a.into_iter().peekable();
+        let mut hits_b = hits_b.into_iter().peekable();
+        loop {
+            let from_a = match (hits_a.peek(), hits_b.peek()) {
+                (Some(a), Some(b)) => a.t <= b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let mut record = if from_a {
+                hits_a.next().unwrap()
+            } else {
+                hits_b.next().unwrap()
+            };
+
+            let was_inside = self.operation.inside(in_a, in_b);
+            let entered = entering(&record);
+            if from_a {
+                in_a = entered;
+            } else {
+                in_b = entered;
+            }
+            let inside = self.operation.inside(in_a, in_b);
+            if inside != was_inside {
+                // Keep the normal pointing out of the result, which turns it
+                // around on surfaces of a carved-out operand.
+                if inside != entered {
+                    record.normal = &record.normal * -1.0;
+                }
+                crossings.push(record);
+            }
+        }
+        crossings
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.crossings(ray, t_min)
+            .into_iter()
+            .next()
+            .filter(|record| record.t < t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+
+    fn hit_all<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hits: &mut Vec<HitRecord<'a>>) {
+        hits.extend(
+            self.crossings(ray, t_min)
+                .into_iter()
+                .take_while(|record| record.t < t_max),
+        );
+    }
+}
@@ -0,0 +1,451 @@
+// A small zlib (RFC 1950) / deflate (RFC 1951) encoder.
+// Matches are found with hash chains over a 32k window and emitted as a
+// single block using the fixed Huffman tables, which keeps the encoder
+// short while still compressing filtered image rows reasonably well.
+
+const WINDOW_SIZE: usize = 1 << 15;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const NO_POSITION: usize = usize::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 256] = build_crc_table();
+
+const fn build_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// Running CRC-32 as used by PNG chunks and gzip.
+pub struct Crc32 {
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { value: 0xffff_ffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut c = self.value;
+        for byte in data {
+            c = CRC_TABLE[((c ^ u32::from(*byte)) & 0xff) as usize] ^ (c >> 8);
+        }
+        self.value = c;
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.value ^ 0xffff_ffff
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    // 5552 is the largest block for which the sums cannot overflow a u32.
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    // CMF: deflate with a 32k window; FLG: default level, check bits so that
+    // (CMF * 256 + FLG) is a multiple of 31.
+    out.push(0x78);
+    out.push(0x9c);
+    deflate_into(data, &mut out);
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn deflate_into(data: &[u8], out: &mut Vec<u8>) {
+    let mut writer = BitWriter::new(out);
+    writer.write_bits(1, 1); // BFINAL
+    writer.write_bits(1, 2); // BTYPE = fixed Huffman
+
+    let mut head = vec![NO_POSITION; HASH_SIZE];
+    let mut prev = vec![NO_POSITION; WINDOW_SIZE];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        if length >= MIN_MATCH {
+            writer.write_length(length);
+            writer.write_distance(distance);
+            for p in pos..pos + length {
+                insert_hash(data, p, &mut head, &mut prev);
+            }
+            pos += length;
+        } else {
+            writer.write_literal(u16::from(data[pos]));
+            insert_hash(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    writer.write_literal(256); // end of block
+    writer.flush();
+}
+
+fn hash_at(data: &[u8], pos: usize) -> usize {
+    let value =
+        (u32::from(data[pos]) << 16) | (u32::from(data[pos + 1]) << 8) | u32::from(data[pos + 2]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert_hash(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH > data.len() {
+        return;
+    }
+    let hash = hash_at(data, pos);
+    prev[pos % WINDOW_SIZE] = head[hash];
+    head[hash] = pos;
+}
+
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best_length = 0;
+    let mut best_distance = 0;
+    let mut candidate = head[hash_at(data, pos)];
+    let mut chain = 0;
+    while candidate != NO_POSITION && chain < MAX_CHAIN {
+        let distance = pos - candidate;
+        if distance > WINDOW_SIZE - 1 {
+            break;
+        }
+
+        if data[candidate + best_length] == data[pos + best_length] {
+            let mut length = 0;
+            while length < max_length && data[candidate + length] == data[pos + length] {
+                length += 1;
+            }
+            if length > best_length {
+                best_length = length;
+                best_distance = distance;
+                if length == max_length {
+                    break;
+                }
+            }
+        }
+
+        let next = prev[candidate % WINDOW_SIZE];
+        // Slots are recycled once the window wraps; stop on stale links.
+        if next == NO_POSITION || next >= candidate {
+            break;
+        }
+        candidate = next;
+        chain += 1;
+    }
+
+    (best_length, best_distance)
+}
+
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        BitWriter {
+            out,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    // Writes the low `count` bits of `bits`, least significant bit first.
+    fn write_bits(&mut self, bits: u32, count: u32) {
+        self.buffer |= u64::from(bits) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are stored most significant bit first.
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits(reversed, length);
+    }
+
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let index = match LENGTH_BASE
+            .iter()
+            .rposition(|base| usize::from(*base) <= length)
+        {
+            Some(index) => index,
+            None => unreachable!("match lengths start at {}", MIN_MATCH),
+        };
+        self.write_literal(257 + index as u16);
+        let extra = u32::from(LENGTH_EXTRA[index]);
+        if extra > 0 {
+            self.write_bits((length - usize::from(LENGTH_BASE[index])) as u32, extra);
+        }
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let index = match DISTANCE_BASE
+            .iter()
+            .rposition(|base| usize::from(*base) <= distance)
+        {
+            Some(index) => index,
+            None => unreachable!("distances start at 1"),
+        };
+        self.write_code(index as u32, 5);
+        let extra = u32::from(DISTANCE_EXTRA[index]);
+        if extra > 0 {
+            self.write_bits((distance - usize::from(DISTANCE_BASE[index])) as u32, extra);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Reads bits least significant first, as deflate packs them.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self.data[self.bit / 8];
+                value |= u32::from((byte >> (self.bit % 8)) & 1) << i;
+                self.bit += 1;
+            }
+            value
+        }
+
+        // Huffman codes are packed most significant bit first.
+        fn code(&mut self, length: u32) -> u32 {
+            (0..length).fold(0, |code, _| (code << 1) | self.bits(1))
+        }
+
+        fn extend_code(&mut self, code: u32, extra: u32) -> u32 {
+            (0..extra).fold(code, |code, _| (code << 1) | self.bits(1))
+        }
+    }
+
+    fn fixed_literal(reader: &mut BitReader<'_>) -> u32 {
+        let code = reader.code(7);
+        if code <= 0x17 {
+            return 256 + code;
+        }
+        let code = reader.extend_code(code, 1);
+        match code {
+            0x30..=0xbf => code - 0x30,
+            0xc0..=0xc7 => 280 + code - 0xc0,
+            _ => 144 + reader.extend_code(code, 1) - 0x190,
+        }
+    }
+
+    // A minimal inflater for stored and fixed Huffman blocks, enough to
+    // check what `zlib_compress` and zlib itself write for short inputs.
+    fn zlib_decompress(stream: &[u8]) -> Vec<u8> {
+        assert_eq!((u32::from(stream[0]) * 256 + u32::from(stream[1])) % 31, 0);
+        let mut reader = BitReader {
+            data: &stream[2..],
+            bit: 0,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let last = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0 => {
+                    reader.bit = reader.bit.div_ceil(8) * 8;
+                    let length = reader.bits(16);
+                    assert_eq!(reader.bits(16), !length & 0xffff);
+                    for _ in 0..length {
+                        out.push(reader.bits(8) as u8);
+                    }
+                }
+                1 => loop {
+                    let symbol = fixed_literal(&mut reader);
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let index = symbol as usize - 257;
+                    let length =
+                        u32::from(LENGTH_BASE[index]) + reader.bits(u32::from(LENGTH_EXTRA[index]));
+                    let index = reader.code(5) as usize;
+                    let distance = u32::from(DISTANCE_BASE[index])
+                        + reader.bits(u32::from(DISTANCE_EXTRA[index]));
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance as usize]);
+                    }
+                },
+                kind => panic!("unexpected block type {}", kind),
+            }
+            if last {
+                break;
+            }
+        }
+        let end = 2 + reader.bit.div_ceil(8);
+        assert_eq!(stream[end..], adler32(&out).to_be_bytes());
+        out
+    }
+
+    // Inputs with zlib's output at level 6 (fixed blocks) and level 0
+    // (stored blocks). For the first four our output is the same byte for
+    // byte; for the others zlib's match search picks other matches, so only
+    // the decoded bytes agree.
+    const ZLIB_OUTPUT: [(&[u8], &str, &str); 7] = [
+        (b"", "789c030000000001", "7801010000ffff00000001"),
+        (b"a", "789c4b040000620062", "7801010100feff6100620062"),
+        (
+            b"hello",
+            "789ccb48cdc9c90700062c0215",
+            "7801010500faff68656c6c6f062c0215",
+        ),
+        (
+            b"The quick brown fox jumps over the lazy dog",
+            "789c0bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456552aa4e4a703005bdc0fda",
+            "7801012b00d4ff54686520717569636b2062726f776e20666f78206a756d7073206f76657220746865206c617a7920646f675bdc0fda",
+        ),
+        (
+            b"abcdefabcdefabcdef",
+            "789c4b4c4a4e494d4b44220142540700",
+            "7801011200edff61626364656661626364656661626364656642540700",
+        ),
+        (
+            b"abcabcabcabc",
+            "789c4b4c4a4e8421001de00499",
+            "7801010c00f3ff6162636162636162636162631de00499",
+        ),
+        (
+            b"aaaaaaaaaaaaaaaaaaaa",
+            "789c4b4cc404004fa60795",
+            "7801011400ebff61616161616161616161616161616161616161614fa60795",
+        ),
+    ];
+
+    #[test]
+    fn compresses_like_zlib() {
+        for (input, fixed, _) in &ZLIB_OUTPUT[..4] {
+            assert_eq!(zlib_compress(input), hex(fixed));
+        }
+        for (input, _, _) in ZLIB_OUTPUT {
+            assert_eq!(zlib_decompress(&zlib_compress(input)), input);
+        }
+    }
+
+    #[test]
+    fn decodes_zlib_streams() {
+        for (input, fixed, stored) in ZLIB_OUTPUT {
+            assert_eq!(zlib_decompress(&hex(fixed)), input);
+            assert_eq!(zlib_decompress(&hex(stored)), input);
+        }
+    }
+
+    #[test]
+    fn round_trips_long_input() {
+        let mut data = Vec::new();
+        let mut state = 1u32;
+        for i in 0..100_000u32 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            // Runs and repeats of varying length, with some noise.
+            data.push(if i % 7 == 0 {
+                (state >> 24) as u8
+            } else {
+                (i / 300 % 5) as u8
+            });
+        }
+        let compressed = zlib_compress(&data);
+        assert!(compressed.len() < data.len() / 2);
+        assert_eq!(zlib_decompress(&compressed), data);
+    }
+
+    #[test]
+    fn checksums() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[]), 1);
+        // Long enough to need the modulo between blocks.
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+}
@@ -0,0 +1,307 @@
+// Single-part scanline OpenEXR writer for linear RGB images.
+// See "OpenEXR File Layout" for the header and chunk formats used here.
+
+use crate::deflate::zlib_compress;
+use crate::vec3::Vec3;
+
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Zip,
+}
+
+const MAGIC: u32 = 20_000_630;
+const VERSION: u32 = 2;
+
+impl PixelType {
+    fn id(self) -> i32 {
+        match self {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Float => 4,
+        }
+    }
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(self) -> usize {
+        match self {
+            Compression::None => 1,
+            Compression::Zip => 16,
+        }
+    }
+}
+
+pub fn write_exr(
+    out: &mut dyn Write,
+    width: usize,
+    height: usize,
+    pixels: &[Vec3],
+    pixel_type: PixelType,
+    compression: Compression,
+) -> io::Result<()> {
+    let header = build_header(width, height, pixel_type, compression);
+
+    let lines_per_block = compression.lines_per_block();
+    let mut chunks = Vec::with_capacity(height.div_ceil(lines_per_block));
+    for y0 in (0..height).step_by(lines_per_block) {
+        let y1 = height.min(y0 + lines_per_block);
+        let raw = pack_scanlines(&pixels[y0 * width..y1 * width], width, pixel_type);
+        let data = match compression {
+            Compression::None => raw,
+            Compression::Zip => {
+                let compressed = zlib_compress(&predict(&interleave(&raw)));
+                // Readers treat a chunk whose size matches the raw size as
+                // uncompressed, so never store a larger "compressed" chunk.
+                if compressed.len() < raw.len() {
+                    compressed
+                } else {
+                    raw
+                }
+            }
+        };
+        chunks.push((y0, data));
+    }
+
+    out.write_all(&header)?;
+
+    let mut offset = (header.len() + 8 * chunks.len()) as u64;
+    for (_, data) in chunks.iter() {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+
+    for (y, data) in chunks.iter() {
+        out.write_all(&(*y as i32).to_le_bytes())?;
+        out.write_all(&(data.len() as i32).to_le_bytes())?;
+        out.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+fn build_header(
+    width: usize,
+    height: usize,
+    pixel_type: PixelType,
+    compression: Compression,
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    // Channels must be listed in alphabetical order.
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"].iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&pixel_type.id().to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        channels.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        channels.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    channels.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channels);
+
+    write_attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[compression.id()],
+    );
+
+    let mut window = Vec::with_capacity(16);
+    for value in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+
+    let mut center = Vec::with_capacity(8);
+    center.extend_from_slice(&0.0f32.to_le_bytes());
+    center.extend_from_slice(&0.0f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+
+    header.push(0);
+    header
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Each scanline stores all of B, then all of G, then all of R.
+fn pack_scanlines(pixels: &[Vec3], width: usize, pixel_type: PixelType) -> Vec<u8> {
+    let mut data = Vec::with_capacity(pixels.len() * 3 * pixel_type.size());
+    for row in pixels.chunks(width) {
+        for channel in [2, 1, 0].iter() {
+            for col in row {
+                let value = *col.get(*channel);
+                match pixel_type {
+                    PixelType::Half => data.extend_from_slice(&f32_to_half(value).to_le_bytes()),
+                    PixelType::Float => data.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+    }
+    data
+}
+
+// ZIP compression first splits the bytes into even and odd halves...
+fn interleave(raw: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(raw.len());
+    result.extend(raw.iter().step_by(2));
+    result.extend(raw.iter().skip(1).step_by(2));
+    result
+}
+
+// ...then delta-encodes them before deflating.
+fn predict(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut previous = 0u8;
+    for (index, value) in data.iter().enumerate() {
+        if index == 0 {
+            result.push(*value);
+        } else {
+            result.push(value.wrapping_sub(previous).wrapping_add(128));
+        }
+        previous = *value;
+    }
+    result
+}
+
+// Round-to-nearest-even conversion to IEEE 754 binary16.
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan_bit = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan_bit;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        // Subnormal half: shift the mantissa, including the implicit bit.
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let truncated = full >> shift;
+        let remainder = full & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let rounded = if remainder > halfway || (remainder == halfway && truncated & 1 == 1) {
+            truncated + 1
+        } else {
+            truncated
+        };
+        return sign | rounded as u16;
+    }
+
+    let truncated = mantissa >> 13;
+    let remainder = mantissa & 0x1fff;
+    let mut half = ((half_exponent as u32) << 10) | truncated;
+    // A carry out of the mantissa correctly bumps the exponent (up to infinity).
+    if remainder > 0x1000 || (remainder == 0x1000 && truncated & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_of_normal_values() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.5), 0xc100);
+        assert_eq!(f32_to_half(0.1), 0x2e66);
+        // The smallest normal half.
+        assert_eq!(f32_to_half(6.103_515_6e-5), 0x0400);
+    }
+
+    #[test]
+    fn half_denormals() {
+        // The smallest subnormal half, 2^-24, and the largest.
+        assert_eq!(f32_to_half(f32::from_bits(0x3380_0000)), 0x0001);
+        assert_eq!(f32_to_half(6.097_555e-5), 0x03ff);
+        // Exactly half of 2^-24 rounds to even, anything above it up.
+        assert_eq!(f32_to_half(f32::from_bits(0x3300_0000)), 0x0000);
+        assert_eq!(f32_to_half(f32::from_bits(0x3300_0001)), 0x0001);
+        assert_eq!(f32_to_half(-1e-10), 0x8000);
+        // f32 denormals are far below the half range.
+        assert_eq!(f32_to_half(f32::from_bits(1)), 0x0000);
+    }
+
+    #[test]
+    fn half_infinities_and_nan() {
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+    }
+
+    #[test]
+    fn half_rounding_at_the_largest_value() {
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65519.0), 0x7bff);
+        // Halfway to the next step rounds to even, which is infinity.
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        // Halfway between 1 and the next half rounds down to even, and up
+        // when the lower neighbour is odd.
+        assert_eq!(f32_to_half(1.0 + 2.0f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+    }
+}
@@ -1,25 +1,46 @@
 use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::packet::{self, RayPacket, PACKET_SIZE};
 use crate::ray::*;
-use crate::rng::Random;
 use crate::vec3::*;
 
-use std::cmp::Ordering;
+pub use crate::bvh::BvhTree;
+
+use std::arch::x86_64::*;
 use std::vec::Vec;
 
 pub trait Hitable: Send + Sync {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
     fn bounding_box(&self) -> Aabb;
-}
 
-pub struct BvhTree {
-    pub root: Box<dyn Hitable>,
-}
+    // Every surface crossing within (t_min, t_max), nearest first, appended
+    // to `hits`. The default repeats `hit` from just past the previous
+    // crossing, which relies on `hit` excluding t_min itself; that also
+    // keeps a crossing on an edge shared by two triangles from counting
+    // twice.
+    fn hit_all<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hits: &mut Vec<HitRecord<'a>>) {
+        let mut t_min = t_min;
+        while let Some(record) = self.hit(ray, t_min, t_max) {
+            if record.t <= t_min {
+                break;
+            }
+            t_min = record.t;
+            hits.push(record);
+        }
+    }
 
-pub struct BvhNode {
-    pub bounding_box: Aabb,
-    pub left: Box<dyn Hitable>,
-    pub right: Box<dyn Hitable>,
+    // `hit` for each ray of the packet set in `active`, each within its own
+    // (t_min, t_max[lane]). The default traces the rays one by one; shapes
+    // that can test all four at once override it, giving the same records.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: i32,
+        t_min: f32,
+        t_max: &[f32; PACKET_SIZE],
+    ) -> [Option<HitRecord<'_>>; PACKET_SIZE] {
+        packet::hit_each(self, packet, active, t_min, t_max)
+    }
 }
 
 pub struct Sphere {
@@ -28,118 +49,183 @@ pub struct Sphere {
     pub material: Box<dyn Material>,
 }
 
-impl BvhTree {
-    pub fn build(hitables: &mut Vec<Box<dyn Hitable>>, rnd: &mut Random) -> Self {
-        BvhTree {
-            root: BvhNode::build_bvh_tree(hitables, rnd),
-        }
+impl Hitable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let material = &*self.material;
+        hit_sphere(&self.center, self.radius, material, ray, t_min, t_max)
     }
-}
 
-impl BvhNode {
-    fn build_bvh_tree(hitables: &mut Vec<Box<dyn Hitable>>, rnd: &mut Random) -> Box<dyn Hitable> {
-        match hitables.len() {
-            1 => return hitables.remove(0),
-            2 => {
-                let left = hitables.remove(0);
-                let right = hitables.remove(0);
-                return Box::new(Self::create(left, right));
-            }
-            _ => {}
-        };
-
-        let axis = (rnd.gen() * 3.0) as usize;
-        hitables.sort_by(|left, right| {
-            let bb_left = *left.bounding_box().min.get(axis);
-            let bb_right = *right.bounding_box().min.get(axis);
-            if bb_left < bb_right {
-                Ordering::Less
-            } else {
-                Ordering::Greater
-            }
-        });
-        let mut split = hitables.split_off(hitables.len() / 2);
-
-        let left = Self::build_bvh_tree(hitables, rnd);
-        let right = Self::build_bvh_tree(&mut split, rnd);
-        Box::new(Self::create(left, right))
+    fn bounding_box(&self) -> Aabb {
+        sphere_box(&self.center, self.radius)
     }
 
-    fn create(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> BvhNode {
-        let bounding_box = Aabb::surrounding_box(&left.bounding_box(), &right.bounding_box());
-        BvhNode {
-            bounding_box,
-            left,
-            right,
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: i32,
+        t_min: f32,
+        t_max: &[f32; PACKET_SIZE],
+    ) -> [Option<HitRecord<'_>>; PACKET_SIZE] {
+        if !packet::has_fma() {
+            return packet::hit_each(self, packet, active, t_min, t_max);
         }
+        let (mask, t) =
+            unsafe { hit_sphere_packet(&self.center, self.radius, packet, t_min, t_max) };
+        let mut records = packet::no_hits();
+        for lane in packet::lanes(mask & active) {
+            records[lane] = Some(sphere_record(
+                &self.center,
+                self.radius,
+                &*self.material,
+                &packet.rays[lane],
+                t[lane],
+            ));
+        }
+        records
     }
 }
 
-impl Hitable for BvhNode {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if self.bounding_box.hit(ray, t_min, t_max) {
-            let hit_left = self.left.hit(ray, t_min, t_max);
-            let hit_right = self.right.hit(ray, t_min, t_max);
-            return match (&hit_left, &hit_right) {
-                (Some(left), Some(right)) => {
-                    if left.t < right.t {
-                        hit_left
-                    } else {
-                        hit_right
-                    }
-                }
-                (Some(_), None) => hit_left,
-                (None, Some(_)) => hit_right,
-                _ => Option::None,
-            };
+// A sphere whose center moves in a straight line from `center0` at `time0`
+// to `center1` at `time1`. It holds still outside that interval, so the box
+// around both ends covers it for any shutter.
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Box<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f32) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
         }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + &(self.center1 - self.center0) * s
+    }
+}
 
-        Option::None
+impl Hitable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let center = self.center(ray.time);
+        hit_sphere(&center, self.radius, &*self.material, ray, t_min, t_max)
     }
 
     fn bounding_box(&self) -> Aabb {
-        self.bounding_box
+        Aabb::surrounding_box(
+            &sphere_box(&self.center0, self.radius),
+            &sphere_box(&self.center1, self.radius),
+        )
     }
 }
 
-impl Hitable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.square_length();
-        let b = dot(&oc, &ray.direction);
-        let c = oc.square_length() - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-        if discriminant > 0.0 {
-            let tmp = (-b - discriminant.sqrt()) / a;
-            if tmp < t_max && tmp > t_min {
-                let hit_point = ray.point_at_parameter(tmp);
-                let record = HitRecord {
-                    t: tmp,
-                    p: hit_point,
-                    normal: &(hit_point - self.center) / self.radius,
-                    material: &*self.material,
-                };
-                return Option::Some(record);
-            }
-
-            let tmp = (-b + discriminant.sqrt()) / a;
-            if tmp < t_max && tmp > t_min {
-                let hit_point = ray.point_at_parameter(tmp);
-                let record = HitRecord {
-                    t: tmp,
-                    p: hit_point,
-                    normal: &(hit_point - self.center) / self.radius,
-                    material: &*self.material,
-                };
-                return Option::Some(record);
-            }
+fn hit_sphere<'a>(
+    center: &Vec3,
+    radius: f32,
+    material: &'a dyn Material,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    let oc = ray.origin - *center;
+    let a = ray.direction.square_length();
+    let b = dot(&oc, &ray.direction);
+    let c = oc.square_length() - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant > 0.0 {
+        let tmp = (-b - discriminant.sqrt()) / a;
+        if tmp < t_max && tmp > t_min {
+            return Option::Some(sphere_record(center, radius, material, ray, tmp));
         }
 
-        Option::None
+        let tmp = (-b + discriminant.sqrt()) / a;
+        if tmp < t_max && tmp > t_min {
+            return Option::Some(sphere_record(center, radius, material, ray, tmp));
+        }
     }
 
-    fn bounding_box(&self) -> Aabb {
-        let radial_length = Vec3::from(self.radius, self.radius, self.radius);
-        Aabb::build(self.center - radial_length, self.center + radial_length)
+    Option::None
+}
+
+// `hit_sphere` for the four rays of a packet, returning the lanes that hit
+// and where. The dot products are summed with FMA in the same order as
+// `dot`, so each lane finds exactly the distance a lone ray would.
+#[target_feature(enable = "fma")]
+unsafe fn hit_sphere_packet(
+    center: &Vec3,
+    radius: f32,
+    packet: &RayPacket,
+    t_min: f32,
+    t_max: &[f32; PACKET_SIZE],
+) -> (i32, [f32; PACKET_SIZE]) {
+    let d = &packet.direction;
+    let oc = [0, 1, 2].map(|axis| _mm_sub_ps(packet.origin[axis], _mm_set1_ps(*center.get(axis))));
+    let dot = |v: &[__m128; 3], w: &[__m128; 3]| {
+        _mm_fmadd_ps(
+            v[0],
+            w[0],
+            _mm_fmadd_ps(v[1], w[1], _mm_fmadd_ps(v[2], w[2], _mm_setzero_ps())),
+        )
+    };
+    let a = dot(d, d);
+    let b = dot(&oc, d);
+    let c = _mm_sub_ps(dot(&oc, &oc), _mm_set1_ps(radius * radius));
+    let discriminant = _mm_sub_ps(_mm_mul_ps(b, b), _mm_mul_ps(a, c));
+    let crosses = _mm_cmpgt_ps(discriminant, _mm_setzero_ps());
+
+    let root = _mm_sqrt_ps(discriminant);
+    let minus_b = _mm_xor_ps(b, _mm_set1_ps(-0.0));
+    let t_min = _mm_set1_ps(t_min);
+    let t_max = _mm_loadu_ps(t_max.as_ptr());
+    let within = |t: __m128| _mm_and_ps(_mm_cmplt_ps(t, t_max), _mm_cmpgt_ps(t, t_min));
+    let near = _mm_div_ps(_mm_sub_ps(minus_b, root), a);
+    let far = _mm_div_ps(_mm_add_ps(minus_b, root), a);
+    let near_within = within(near);
+    let t = _mm_or_ps(
+        _mm_and_ps(near_within, near),
+        _mm_andnot_ps(near_within, far),
+    );
+    let hits = _mm_and_ps(crosses, _mm_or_ps(near_within, within(far)));
+
+    let mut lanes = [0.0; PACKET_SIZE];
+    _mm_storeu_ps(lanes.as_mut_ptr(), t);
+    (_mm_movemask_ps(hits), lanes)
+}
+
+fn sphere_record<'a>(
+    center: &Vec3,
+    radius: f32,
+    material: &'a dyn Material,
+    ray: &Ray,
+    t: f32,
+) -> HitRecord<'a> {
+    let hit_point = ray.point_at_parameter(t);
+    let normal = &(hit_point - *center) / radius;
+    let (u, v) = sphere_uv(&normal);
+    HitRecord {
+        t,
+        p: hit_point,
+        normal,
+        u,
+        v,
+        colour: Vec3::from(1.0, 1.0, 1.0),
+        material,
     }
 }
+
+fn sphere_box(center: &Vec3, radius: f32) -> Aabb {
+    let radial_length = Vec3::from(radius, radius, radius);
+    Aabb::build(center - &radial_length, center + &radial_length)
+}
+
+// Longitude and latitude of a point on the unit sphere, both mapped to [0, 1].
+fn sphere_uv(point: &Vec3) -> (f32, f32) {
+    let phi = point.z().atan2(*point.x());
+    let theta = point.y().clamp(-1.0, 1.0).asin();
+    (
+        1.0 - (phi + std::f32::consts::PI) / (2.0 * std::f32::consts::PI),
+        (theta + std::f32::consts::FRAC_PI_2) / std::f32::consts::PI,
+    )
+}
@@ -0,0 +1,259 @@
+use crate::deflate::{zlib_compress, Crc32};
+use crate::exr::{self, write_exr};
+use crate::rgbe::write_hdr;
+use crate::vec3::Vec3;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    PpmAscii,
+    PpmBinary,
+    Png,
+    Hdr,
+    Exr,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::PpmBinary),
+            "png" => Some(ImageFormat::Png),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct OutputOptions {
+    pub format: ImageFormat,
+    // Used by the PNG and PPM writers.
+    pub bit_depth: BitDepth,
+    pub exr_pixel_type: exr::PixelType,
+    pub exr_compression: exr::Compression,
+}
+
+impl OutputOptions {
+    // 8-bit channels and half-float ZIP compressed EXR.
+    pub fn for_format(format: ImageFormat) -> Self {
+        OutputOptions {
+            format,
+            bit_depth: BitDepth::Eight,
+            exr_pixel_type: exr::PixelType::Half,
+            exr_compression: exr::Compression::Zip,
+        }
+    }
+}
+
+pub struct Image<'a> {
+    pub width: usize,
+    pub height: usize,
+    // Linear radiance, top row first. The 8/16-bit formats gamma correct and
+    // clamp on the way out; HDR and EXR store the values untouched.
+    pub pixels: &'a [Vec3],
+}
+
+impl<'a> Image<'a> {
+    pub fn save(&self, path: &Path, options: &OutputOptions) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, options)?;
+        out.flush()
+    }
+
+    pub fn write(&self, out: &mut dyn Write, options: &OutputOptions) -> io::Result<()> {
+        assert_eq!(self.pixels.len(), self.width * self.height);
+        let depth = options.bit_depth;
+        match options.format {
+            ImageFormat::PpmAscii => self.write_ppm_ascii(out, depth),
+            ImageFormat::PpmBinary => self.write_ppm_binary(out, depth),
+            ImageFormat::Png => self.write_png(out, depth),
+            ImageFormat::Hdr => write_hdr(out, self.width, self.height, self.pixels),
+            ImageFormat::Exr => write_exr(
+                out,
+                self.width,
+                self.height,
+                self.pixels,
+                options.exr_pixel_type,
+                options.exr_compression,
+            ),
+        }
+    }
+
+    fn write_ppm_ascii(&self, out: &mut dyn Write, depth: BitDepth) -> io::Result<()> {
+        write!(
+            out,
+            "P3\n{} {}\n{}\n",
+            self.width,
+            self.height,
+            max_value(depth)
+        )?;
+        for col in self.pixels {
+            let [r, g, b] = quantise(col, depth);
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
+    }
+
+    fn write_ppm_binary(&self, out: &mut dyn Write, depth: BitDepth) -> io::Result<()> {
+        write!(
+            out,
+            "P6\n{} {}\n{}\n",
+            self.width,
+            self.height,
+            max_value(depth)
+        )?;
+        out.write_all(&self.samples(depth))
+    }
+
+    fn write_png(&self, out: &mut dyn Write, depth: BitDepth) -> io::Result<()> {
+        const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        const COLOUR_TYPE_RGB: u8 = 2;
+
+        let bit_depth: u8 = match depth {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        };
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.push(bit_depth);
+        header.push(COLOUR_TYPE_RGB);
+        header.push(0); // compression: deflate
+        header.push(0); // filter method: adaptive
+        header.push(0); // interlace: none
+
+        let bytes_per_pixel = 3 * usize::from(bit_depth / 8);
+        let filtered = filter_scanlines(
+            &self.samples(depth),
+            self.width * bytes_per_pixel,
+            bytes_per_pixel,
+        );
+
+        out.write_all(&SIGNATURE)?;
+        write_chunk(out, b"IHDR", &header)?;
+        write_chunk(out, b"IDAT", &zlib_compress(&filtered))?;
+        write_chunk(out, b"IEND", &[])
+    }
+
+    // Packed RGB samples, big-endian for 16-bit as both PNG and PPM expect.
+    fn samples(&self, depth: BitDepth) -> Vec<u8> {
+        let bytes_per_sample = match depth {
+            BitDepth::Eight => 1,
+            BitDepth::Sixteen => 2,
+        };
+        let mut samples = Vec::with_capacity(self.pixels.len() * 3 * bytes_per_sample);
+        for col in self.pixels {
+            for value in quantise(col, depth).iter() {
+                match depth {
+                    BitDepth::Eight => samples.push(*value as u8),
+                    BitDepth::Sixteen => samples.extend_from_slice(&(*value as u16).to_be_bytes()),
+                }
+            }
+        }
+        samples
+    }
+}
+
+fn max_value(depth: BitDepth) -> u32 {
+    match depth {
+        BitDepth::Eight => 255,
+        BitDepth::Sixteen => 65535,
+    }
+}
+
+// Gamma 2 display transform followed by clamping to the output range.
+fn quantise(col: &Vec3, depth: BitDepth) -> [u32; 3] {
+    let scale = max_value(depth) as f32 + 0.99;
+    let channel = |value: f32| (scale * value.sqrt().clamp(0.0, 1.0)) as u32;
+    [channel(*col.r()), channel(*col.g()), channel(*col.b())]
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc.finish().to_be_bytes())
+}
+
+// Applies one of the five PNG filters to every row, picking the filter with
+// the smallest sum of absolute residuals (the heuristic libpng uses).
+fn filter_scanlines(samples: &[u8], stride: usize, bytes_per_pixel: usize) -> Vec<u8> {
+    let rows = samples.len() / stride;
+    let mut out = Vec::with_capacity(rows * (stride + 1));
+    let zero_row = vec![0u8; stride];
+    let mut candidates = vec![vec![0u8; stride]; 5];
+
+    for y in 0..rows {
+        let row = &samples[y * stride..(y + 1) * stride];
+        let above = if y == 0 {
+            &zero_row[..]
+        } else {
+            &samples[(y - 1) * stride..y * stride]
+        };
+
+        for i in 0..stride {
+            let a = if i >= bytes_per_pixel {
+                row[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let b = above[i];
+            let c = if i >= bytes_per_pixel {
+                above[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let x = row[i];
+            candidates[0][i] = x;
+            candidates[1][i] = x.wrapping_sub(a);
+            candidates[2][i] = x.wrapping_sub(b);
+            candidates[3][i] = x.wrapping_sub(((u16::from(a) + u16::from(b)) / 2) as u8);
+            candidates[4][i] = x.wrapping_sub(paeth(a, b, c));
+        }
+
+        let cost = |filtered: &Vec<u8>| -> u64 {
+            filtered
+                .iter()
+                .map(|value| u64::from((*value as i8).unsigned_abs()))
+                .sum()
+        };
+        let best = (0..5)
+            .min_by_key(|filter| cost(&candidates[*filter]))
+            .unwrap_or(0);
+
+        out.push(best as u8);
+        out.extend_from_slice(&candidates[best]);
+    }
+
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
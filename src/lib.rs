@@ -0,0 +1,67 @@
+//! A small CPU ray tracer following "Ray Tracing in One Weekend".
+//!
+//! ```no_run
+//! use raytracer::*;
+//!
+//! let settings = RenderSettings::default();
+//! let camera = CameraSettings::default().build(settings.aspect_ratio());
+//! let mut rnd = Random::create_with_seed(settings.seed);
+//! let mut objects = random_scene(&mut rnd);
+//! let world = World::build(&mut objects, &mut rnd);
+//!
+//! let framebuffer = render(camera, world, &settings);
+//! let output = OutputOptions::for_format(ImageFormat::Png);
+//! framebuffer.save(std::path::Path::new("out.png"), &output).unwrap();
+//! ```
+
+pub mod aabb;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod csg;
+mod deflate;
+pub mod exr;
+pub mod hitable;
+pub mod image;
+pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod obj;
+pub mod packet;
+pub mod ply;
+pub mod progress;
+mod qbvh;
+pub mod quadrics;
+pub mod ray;
+pub mod render;
+mod rgbe;
+pub mod rng;
+pub mod scene;
+pub mod sdf;
+pub mod shapes;
+pub mod tiles;
+pub mod transform;
+pub mod vec3;
+pub mod world;
+
+pub use bvh::{BvhBuilder, BvhSettings, BvhTree};
+pub use camera::{Camera, CameraSettings};
+pub use csg::{Csg, CsgOperation};
+pub use hitable::{Hitable, MovingSphere, Sphere};
+pub use image::{BitDepth, Image, ImageFormat, OutputOptions};
+pub use material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+pub use medium::ConstantMedium;
+pub use mesh::{MeshError, Triangle, TriangleMesh};
+pub use obj::{load_obj, ObjError, ObjModel};
+pub use packet::{RayPacket, PACKET_SIZE};
+pub use ply::{load_ply, PlyError, PlyMesh};
+pub use progress::{Progress, ProgressBar};
+pub use quadrics::{Cone, Cylinder, Disk, Paraboloid};
+pub use render::{render, render_progressive, Framebuffer, RenderSettings};
+pub use rng::Random;
+pub use scene::{load_scene, parse_scene, random_scene, Scene, SceneError};
+pub use sdf::{DistanceFunction, SdfShape};
+pub use shapes::{AxisAlignedRect, BoxShape, InfinitePlane, Plane, Quad};
+pub use transform::{AnimatedTransformed, Keyframe, Matrix4, Transformed};
+pub use vec3::Vec3;
+pub use world::World;
@@ -1,257 +1,206 @@
-mod aabb;
-mod camera;
-mod hitable;
-mod material;
-mod ray;
-mod rng;
-mod vec3;
+mod cli;
 
-use camera::Camera;
-use hitable::*;
-use material::*;
-use ray::Ray;
-use rng::Random;
-use std::f32;
-use vec3::Vec3;
+use cli::{Command, Options, ProgressMode, SceneKind};
+use raytracer::checkpoint::{Checkpoint, SceneHasher};
+use raytracer::{
+    render_progressive, BvhSettings, Framebuffer, Hitable, ProgressBar, Random, World,
+};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 fn main() {
-    let mut rnd = Random::create_with_seed(42);
-    let nx = 3840;
-    let ny = 2160;
-    let nxd = nx as f32;
-    let nyd = ny as f32;
-
-    let look_from = Vec3::from(13.0, 2.0, 3.0);
-    let look_at = Vec3::from(0.0, 0.0, 0.0);
-    let aperture = 0.1;
-    let dist_to_focus = 10.0;
-
-    let camera = Camera::build(
-        &look_from,
-        &look_at,
-        &Vec3::from(0.0, 1.0, 0.0),
-        20.0,
-        nxd / nyd,
-        aperture,
-        dist_to_focus,
-    );
-
-    let mut hitable_list = random_scene(&mut rnd);
-
-    let bvh_tree = BvhTree::build(&mut hitable_list, &mut rnd);
-
-    let samples_per_pixel = 1024;
-    let thread_count = 24;
-
-    // let cols = render_single_thread(&camera, nx, ny, samples_per_pixel, &bvh_tree, &mut rnd);
-    let cols = render_multi_thread(
-        camera,
-        nx,
-        ny,
-        samples_per_pixel,
-        bvh_tree,
-        &mut rnd,
-        thread_count,
-    );
-
-    print!("P3\n{} {}\n255\n", nx, ny);
-    for col in cols.iter() {
-        let ir = (255.99 * col.r()) as i32;
-        let ig = (255.99 * col.g()) as i32;
-        let ib = (255.99 * col.b()) as i32;
-        println!("{} {} {}", ir, ig, ib);
-    }
-}
-
-fn render_multi_thread(
-    camera: Camera,
-    nx: usize,
-    ny: usize,
-    samples_per_pixel: i16,
-    bvh_tree: BvhTree,
-    _: &mut Random,
-    thread_count: usize,
-) -> Vec<Vec3> {
-    let mut cols: Vec<Vec3> = Vec::with_capacity(nx * ny);
-    let mut workers: Vec<std::thread::JoinHandle<std::vec::Vec<vec3::Vec3>>> =
-        Vec::with_capacity(thread_count);
-    let nxd = nx as f32;
-    let nyd = ny as f32;
-
-    let arc_tree = std::sync::Arc::new(bvh_tree);
-
-    for thread_index in (0..thread_count).rev() {
-        let local_bvh = arc_tree.clone();
-        let thread_seed = 1234 * thread_index as u64;
-        let (y0, y1) = get_segment(thread_count, thread_index, ny);
-
-        let thd = std::thread::spawn(move || {
-            let mut rnd = Random::create_with_seed(thread_seed);
-            let mut cols: Vec<Vec3> = Vec::with_capacity(nx * ny);
-            for y in (y0..y1).rev() {
-                let yd = y as f32;
-                for x in 0..nx {
-                    let mut col = Vec3::from(0.0, 0.0, 0.0);
-                    let xd = x as f32;
-                    for _ in 0..samples_per_pixel {
-                        let u = (xd + rnd.gen()) / nxd;
-                        let v = (yd + rnd.gen()) / nyd;
-                        let r = camera.get_ray(u, v, &mut rnd);
-                        col += &colour(&r, local_bvh.as_ref(), &mut rnd, 1);
-                    }
-
-                    col /= f32::from(samples_per_pixel);
-                    col = Vec3::from(col.x().sqrt(), col.y().sqrt(), col.z().sqrt());
-                    cols.push(col);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut options = parse_args_or_exit(&args, Options::default());
+
+    // Scene files may carry their own settings; the command line is applied
+    // again on top of them so that explicit options still win.
+    let mut model_files = Vec::new();
+    let mut hitable_list = match &options.scene {
+        SceneKind::Random => None,
+        SceneKind::File(path) => match raytracer::load_scene(Path::new(path)) {
+            Ok(scene) => {
+                let mut defaults = Options::default();
+                scene
+                    .settings
+                    .apply(&mut defaults.render, &mut defaults.camera);
+                options = parse_args_or_exit(&args, defaults);
+                for warning in &scene.warnings {
+                    eprintln!("warning: {}", warning);
                 }
+                model_files = scene.files;
+                Some(scene.hitables)
             }
+            Err(err) => {
+                eprintln!("error: {}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+    };
 
-            cols
-        });
-
-        workers.push(thd);
+    if let Err(err) = options.validate() {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
     }
 
-    for waiter in workers {
-        let mut result = waiter.join().unwrap();
-        cols.append(&mut result);
+    let mut rnd = Random::create_with_seed(options.render.seed);
+    let hitable_list = hitable_list.get_or_insert_with(|| raytracer::random_scene(&mut rnd));
+    if hitable_list.is_empty() {
+        eprintln!("error: the scene does not contain any objects");
+        std::process::exit(1);
     }
 
-    cols
+    let scene_hash = scene_hash(&options, &model_files);
+    if let Err(err) = run(&options, hitable_list, &mut rnd, scene_hash) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
 
-/*
-fn render_single_thread(
-    camera: &Camera,
-    nx: usize,
-    ny: usize,
-    samples_per_pixel: i16,
-    bvh_tree: &BvhTree,
-    rnd: &mut Random,
-) -> Vec<Vec3> {
-    let nxd = nx as f32;
-    let nyd = ny as f32;
-
-    let mut cols: Vec<Vec3> = Vec::with_capacity(nx * ny);
-    for y in (0..ny).rev() {
-        let yd = y as f32;
-        for x in 0..nx {
-            let mut col = Vec3::from(0.0, 0.0, 0.0);
-            let xd = x as f32;
-            for _ in 0..samples_per_pixel {
-                let u = (xd + rnd.gen()) / nxd;
-                let v = (yd + rnd.gen()) / nyd;
-                let r = camera.get_ray(u, v, rnd);
-                col += &colour(&r, &bvh_tree, rnd, 1);
-            }
-
-            col /= f32::from(samples_per_pixel);
-            col = Vec3::from(col.x().sqrt(), col.y().sqrt(), col.z().sqrt());
-            cols.push(col);
+fn parse_args_or_exit(args: &[String], defaults: Options) -> Options {
+    match cli::parse_args(args.iter().cloned(), defaults) {
+        Ok(Command::Render(options)) => *options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("Run with --help for a list of options.");
+            std::process::exit(2);
         }
     }
-
-    cols
 }
-*/ 
 
-fn colour(ray: &Ray, world: &BvhTree, rnd: &mut Random, depth: i32) -> Vec3 {
-    const MAX_THING: f32 = 1.0e10;
-    let record = world.root.hit(ray, 0.001, MAX_THING);
-    match record {
-        None => {
-            // Render "Sky"
-            let direction = ray.direction.make_normalised();
-            let t = 0.5 * (direction.y() + 1.0);
-
-            (&Vec3::from(1.0, 1.0, 1.0) * (1.0 - t)) + (&Vec3::from(0.5, 0.7, 1.0) * t)
-        }
-        Some(rec) => {
-            let mut scattered = Ray::default();
-            let mut attenuation = Vec3::default();
-            if depth < 20
-                && rec
-                    .material
-                    .scatter(ray, &rec, rnd, &mut attenuation, &mut scattered)
-            {
-                attenuation.direct_product(&colour(&scattered, world, rnd, depth + 1))
-            } else {
-                Vec3::from(0.0, 0.0, 0.0)
-            }
+// Identifies everything that feeds into the rendered samples, so a
+// checkpoint is only resumed for the render that wrote it. Scheduling
+// settings (threads, tiles, pass size) do not change the image. The models
+// a scene file loads are hashed along with it, so editing one of them also
+// invalidates the checkpoint.
+fn scene_hash(options: &Options, model_files: &[PathBuf]) -> u64 {
+    let mut hasher = SceneHasher::new();
+    match &options.scene {
+        SceneKind::Random => hasher.write(b"random"),
+        SceneKind::File(path) => {
+            hasher.write(b"file");
+            hasher.write(&std::fs::read(path).unwrap_or_default());
         }
     }
+    for path in model_files {
+        let contents = std::fs::read(path).unwrap_or_default();
+        hasher.write_u64(contents.len() as u64);
+        hasher.write(&contents);
+    }
+    let render = &options.render;
+    for value in [
+        render.width as u64,
+        render.height as u64,
+        render.max_depth as u64,
+        render.seed,
+    ]
+    .iter()
+    {
+        hasher.write_u64(*value);
+    }
+    let camera = &options.camera;
+    for vector in [camera.look_from, camera.look_at, camera.up].iter() {
+        hasher.write_f32(*vector.x());
+        hasher.write_f32(*vector.y());
+        hasher.write_f32(*vector.z());
+    }
+    hasher.write_f32(camera.vertical_fov_degrees);
+    hasher.write_f32(camera.aperture);
+    hasher.write_f32(camera.focus_distance);
+    hasher.write_f32(camera.shutter_open);
+    hasher.write_f32(camera.shutter_close);
+    hasher.finish()
 }
 
-fn get_segment(thread_count: usize, thread_index: usize, ny: usize) -> (usize, usize) {
-    let segment_size = ny / thread_count;
-    let lower = segment_size * thread_index;
-    let upper = if thread_index == thread_count - 1 {
-        ny
-    } else {
-        segment_size * (thread_index + 1)
+fn run(
+    options: &Options,
+    hitable_list: &mut Vec<Box<dyn Hitable>>,
+    rnd: &mut Random,
+    scene_hash: u64,
+) -> io::Result<()> {
+    let nx = options.render.width;
+    let ny = options.render.height;
+    let camera = options.camera.build(options.render.aspect_ratio());
+
+    let bvh = BvhSettings {
+        thread_count: options.render.thread_count,
+        ..options.bvh
     };
-    (lower, upper)
-}
-
-fn random_scene(rnd: &mut Random) -> Vec<Box<dyn Hitable>> {
-    let n = 500;
-    let mut list: Vec<Box<dyn Hitable>> = Vec::with_capacity(n + 1);
-    list.push(build_sphere(
-        Vec3::from(0.0, -1000.0, 0.0),
-        1000.0,
-        Box::new(Lambertian::with_albedo(Vec3::from(0.5, 0.5, 0.5))),
-    ));
-    for a in -11..11i16 {
-        for b in -11..11i16 {
-            let choose_mat = rnd.gen();
-            let center = Vec3::from(
-                f32::from(a) + 0.9 * rnd.gen(),
-                0.2,
-                f32::from(b) + 0.9 * rnd.gen(),
-            );
-            if (center - Vec3::from(4.0, 0.2, 0.0)).length() > 0.9 {
-                let material: Box<dyn Material> = match choose_mat {
-                    x if x < 0.8 => Box::new(Lambertian::with_albedo(Vec3::from(
-                        rnd.gen() * rnd.gen(),
-                        rnd.gen() * rnd.gen(),
-                        rnd.gen() * rnd.gen(),
-                    ))),
-                    x if x < 0.95 => Box::new(Metal::with_albedo(Vec3::from(
-                        0.5 * (1.0 + rnd.gen()),
-                        0.5 * (1.0 + rnd.gen()),
-                        0.5 * (1.0 + rnd.gen()),
-                    ))),
-                    _ => Box::new(Dielectric::with_refraction_index(1.5)),
-                };
-                list.push(build_sphere(center, 0.2, material));
+    let world = World::build_with(hitable_list, rnd, &bvh);
+
+    let mut state = match &options.checkpoint {
+        Some(path) if options.resume && Path::new(path).exists() => {
+            let state = Checkpoint::load(Path::new(path)).map_err(|err| {
+                io::Error::new(err.kind(), format!("cannot resume from {}: {}", path, err))
+            })?;
+            if state.width != nx || state.height != ny || state.scene_hash != scene_hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} was written for a different scene or render settings",
+                        path
+                    ),
+                ));
             }
+            eprintln!(
+                "Resuming from {} after {} passes",
+                path, state.passes_completed
+            );
+            state
         }
-    }
+        _ => Checkpoint::new(nx, ny, options.render.seed, scene_hash),
+    };
 
-    list.push(build_sphere(
-        Vec3::from(0.0, 1.0, 0.0),
-        1.0,
-        Box::new(Dielectric::with_refraction_index(1.5)),
-    ));
-    list.push(build_sphere(
-        Vec3::from(-4.0, 1.0, 0.0),
-        1.0,
-        Box::new(Lambertian::with_albedo(Vec3::from(0.4, 0.2, 0.1))),
-    ));
-    list.push(build_sphere(
-        Vec3::from(4.0, 1.0, 0.0),
-        1.0,
-        Box::new(Metal::with_albedo(Vec3::from(0.7, 0.6, 0.5))),
-    ));
+    let mut last_save = Instant::now();
+    let mut save_checkpoint = |state: &Checkpoint, force: bool| {
+        if let Some(path) = &options.checkpoint {
+            if force || last_save.elapsed().as_secs_f32() >= options.checkpoint_interval {
+                if let Err(err) = state.save(Path::new(path)) {
+                    eprintln!("warning: could not write checkpoint {}: {}", path, err);
+                }
+                last_save = Instant::now();
+            }
+        }
+    };
 
-    list
-}
+    let show_progress = match options.progress {
+        ProgressMode::Auto => io::stderr().is_terminal(),
+        ProgressMode::Always => true,
+        ProgressMode::Never => false,
+    };
+    let mut progress_bar = ProgressBar::new();
+    render_progressive(
+        camera,
+        world,
+        &options.render,
+        &mut state,
+        &mut |state| save_checkpoint(state, false),
+        &mut |progress| {
+            if show_progress {
+                progress_bar.update(progress);
+            }
+        },
+    );
+    progress_bar.finish();
+    save_checkpoint(&state, true);
 
-fn build_sphere(center: Vec3, radius: f32, material: Box<dyn Material>) -> Box<dyn Hitable> {
-    let sphere = Sphere {
-        center,
-        radius,
-        material,
+    let framebuffer = Framebuffer {
+        width: nx,
+        height: ny,
+        pixels: state.average(),
     };
-    Box::new(sphere)
+    match &options.output {
+        Some(path) => framebuffer.save(Path::new(path), &options.image),
+        None => {
+            let image = framebuffer.image();
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            image.write(&mut out, &options.image)?;
+            out.flush()
+        }
+    }
 }
@@ -11,6 +11,11 @@ pub trait Material: Send + Sync {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool;
+
+    // Light given off at the hit point, added on top of whatever is scattered.
+    fn emitted(&self, _rec: &HitRecord) -> Vec3 {
+        Vec3::default()
+    }
 }
 
 pub struct Lambertian {
@@ -25,6 +30,17 @@ pub struct Dielectric {
     refraction_index: f32,
 }
 
+// Emits light evenly in all directions and scatters nothing.
+pub struct DiffuseLight {
+    emission: Vec3,
+}
+
+// Scatters into a uniformly random direction, the phase function of a
+// participating medium such as fog or smoke.
+pub struct Isotropic {
+    albedo: Vec3,
+}
+
 impl Lambertian {
     pub fn with_albedo(albedo: Vec3) -> Lambertian {
         Lambertian { albedo }
@@ -43,7 +59,7 @@ impl Material for Lambertian {
         let target = rec.p + rec.normal + random_in_unit_sphere(rnd);
         scattered.origin = rec.p;
         scattered.direction = target - rec.p;
-        attenuation.set(&self.albedo);
+        attenuation.set(&self.albedo.direct_product(&rec.colour));
         true
     }
 }
@@ -126,3 +142,48 @@ impl Material for Dielectric {
         true
     }
 }
+
+impl DiffuseLight {
+    pub fn with_emission(emission: Vec3) -> DiffuseLight {
+        DiffuseLight { emission }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _rec: &HitRecord,
+        _rnd: &mut Random,
+        _attenuation: &mut Vec3,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Vec3 {
+        self.emission
+    }
+}
+
+impl Isotropic {
+    pub fn with_albedo(albedo: Vec3) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        rec: &HitRecord,
+        rnd: &mut Random,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        scattered.origin = rec.p;
+        scattered.direction = random_in_unit_sphere(rnd);
+        attenuation.set(&self.albedo.direct_product(&rec.colour));
+        true
+    }
+}
@@ -0,0 +1,137 @@
+// Homogeneous participating media from "Ray Tracing: The Next Week": fog or
+// smoke filling the inside of a boundary shape.
+
+use crate::aabb::Aabb;
+use crate::hitable::Hitable;
+use crate::material::{Isotropic, Material};
+use crate::ray::*;
+use crate::rng;
+use crate::vec3::*;
+
+use std::sync::Arc;
+
+// The smallest step past the entry, in units of the ray parameter.
+const EXIT_MARGIN: f32 = 1.0e-4;
+
+// A volume of constant density inside `boundary`, which must be closed and
+// convex: a ray is taken to be inside between the first two boundary hits.
+// Rays travel a random, exponentially distributed distance before
+// scattering, so thin fog lets most light through and dense smoke little.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hitable>,
+    density: f32,
+    phase_function: Box<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hitable>, density: f32, albedo: Vec3) -> Self {
+        ConstantMedium {
+            boundary,
+            density,
+            phase_function: Box::new(Isotropic::with_albedo(albedo)),
+        }
+    }
+}
+
+impl Hitable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // Entry and exit along the whole line, so rays starting inside the
+        // volume still find where they leave it.
+        let entry = self.boundary.hit(ray, f32::NEG_INFINITY, f32::INFINITY)?.t;
+        let exit = self
+            .boundary
+            .hit(ray, entry + exit_margin(ray, entry), f32::INFINITY)?
+            .t;
+
+        // The ray's sample is shared by every medium it crosses. Mixing in
+        // where it enters this one gives each crossing its own value, so
+        // the chance of getting through two volumes is the product of
+        // their transmittances.
+        let sample = rng::unit_from_hash(rng::mix_seed(&[
+            ray.medium_sample.to_bits() as u64,
+            entry.to_bits() as u64,
+        ]));
+
+        let entry = entry.max(t_min);
+        let exit = exit.min(t_max);
+        if entry >= exit {
+            return None;
+        }
+
+        let ray_length = ray.direction.length();
+        let distance_inside = (exit - entry) * ray_length;
+        let hit_distance = -(1.0 / self.density) * sample.ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = entry + hit_distance / ray_length;
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            // Scattering inside a medium has no surface, any normal will do.
+            normal: Vec3::from(1.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material: &*self.phase_function,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+// How far past the entry to look for the exit, so the entry is not found
+// again. Rounding error in the entry hit grows with the coordinates, so
+// far from the origin the margin does too, as in `Aabb::padded`.
+fn exit_margin(ray: &Ray, entry: f32) -> f32 {
+    let point = ray.point_at_parameter(entry);
+    let magnitude = (0..3)
+        .map(|axis| point.get(axis).abs().max(ray.origin.get(axis).abs()))
+        .fold(0.0, f32::max);
+    EXIT_MARGIN.max(magnitude * f32::EPSILON * 4.0 / ray.direction.length())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::rng::Random;
+    use crate::shapes::BoxShape;
+
+    fn slab(min_x: f32, max_x: f32, density: f32) -> ConstantMedium {
+        let boundary = BoxShape::new(
+            Vec3::from(min_x, -1.0, -1.0),
+            Vec3::from(max_x, 1.0, 1.0),
+            Box::new(Lambertian::with_albedo(Vec3::from(0.5, 0.5, 0.5))),
+        );
+        ConstantMedium::new(Arc::new(boundary), density, Vec3::from(0.5, 0.5, 0.5))
+    }
+
+    #[test]
+    fn crossings_of_two_media_are_independent() {
+        // Each slab lets through exp(-0.5) of the light; both together
+        // exp(-1), not the exp(-0.5) that one shared sample would give.
+        let first = slab(0.0, 0.5, 1.0);
+        let second = slab(1.0, 2.0, 0.5);
+        let mut rnd = Random::create_with_seed(1);
+        let rays = 20000;
+        let mut passed = 0;
+        for _ in 0..rays {
+            let ray = Ray {
+                origin: Vec3::from(-1.0, 0.0, 0.0),
+                direction: Vec3::from(1.0, 0.0, 0.0),
+                medium_sample: rnd.gen_separate(),
+                ..Ray::default()
+            };
+            if first.hit(&ray, 0.0, f32::MAX).is_none() && second.hit(&ray, 0.0, f32::MAX).is_none()
+            {
+                passed += 1;
+            }
+        }
+        let rate = passed as f32 / rays as f32;
+        assert!((rate - (-1.0f32).exp()).abs() < 0.02, "{}", rate);
+    }
+}
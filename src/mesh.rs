@@ -0,0 +1,600 @@
+// Indexed triangle meshes. The vertex buffers are shared between all the
+// triangles of a mesh; each `Triangle` only stores its index, so a mesh can
+// be split into individual hitables for the BVH without copying vertices.
+
+use crate::aabb::{Aabb, MIN_BOX_EXTENT};
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::packet::{self, RayPacket, PACKET_SIZE};
+use crate::ray::*;
+use crate::vec3::*;
+
+use std::arch::x86_64::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    // Per-vertex shading normals and texture coordinates; empty when the
+    // mesh has none, in which case the geometric normal and the barycentric
+    // coordinates are used instead.
+    normals: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    // Per-vertex colours, handed to the material through the hit record.
+    colours: Vec<Vec3>,
+    indices: Vec<[u32; 3]>,
+    material: Box<dyn Material>,
+    // Whether the triangles enclose a solid, with every edge shared by two
+    // of them. Open meshes turn their normals toward the ray, like the open
+    // quadrics, so that both sides shade alike; closed ones keep them as
+    // wound for dielectrics and CSG, which need to tell inside from out.
+    closed: bool,
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MeshError {
+    IndexOutOfRange {
+        triangle: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    AttributeCount {
+        attribute: &'static str,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::IndexOutOfRange {
+                triangle,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "triangle {} uses vertex {} but the mesh only has {} vertices",
+                triangle, index, vertex_count
+            ),
+            MeshError::AttributeCount {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} {} (one per vertex), found {}",
+                expected, attribute, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+        material: Box<dyn Material>,
+    ) -> Result<Self, MeshError> {
+        for (triangle, corners) in indices.iter().enumerate() {
+            if let Some(&index) = corners
+                .iter()
+                .find(|&&index| index as usize >= positions.len())
+            {
+                return Err(MeshError::IndexOutOfRange {
+                    triangle,
+                    index,
+                    vertex_count: positions.len(),
+                });
+            }
+        }
+
+        let closed = is_closed(&positions, &indices);
+        Ok(TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colours: Vec::new(),
+            indices,
+            material,
+            closed,
+        })
+    }
+
+    // Enables smooth shading. Normals need not be normalised.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Result<Self, MeshError> {
+        check_count("normals", self.positions.len(), normals.len())?;
+        self.normals = normals;
+        Ok(self)
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<[f32; 2]>) -> Result<Self, MeshError> {
+        check_count("texture coordinates", self.positions.len(), uvs.len())?;
+        self.uvs = uvs;
+        Ok(self)
+    }
+
+    // Colours are multiplied into the Lambertian albedo.
+    pub fn with_colours(mut self, colours: Vec<Vec3>) -> Result<Self, MeshError> {
+        check_count("colours", self.positions.len(), colours.len())?;
+        self.colours = colours;
+        Ok(self)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    // One hitable per triangle, ready to be added to a scene so that the
+    // BVH is built over the individual triangles.
+    pub fn into_triangles(self) -> Vec<Box<dyn Hitable>> {
+        let mesh = Arc::new(self);
+        (0..mesh.indices.len())
+            .map(|index| {
+                Box::new(Triangle {
+                    mesh: mesh.clone(),
+                    index,
+                }) as Box<dyn Hitable>
+            })
+            .collect()
+    }
+
+    fn corners(&self, index: usize) -> [&Vec3; 3] {
+        let [a, b, c] = self.indices[index];
+        [
+            &self.positions[a as usize],
+            &self.positions[b as usize],
+            &self.positions[c as usize],
+        ]
+    }
+
+    fn hit_triangle(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord<'_>> {
+        let (t, barycentric) = intersect_triangle(ray, self.corners(index), t_min, t_max)?;
+        Some(self.triangle_record(index, ray, t, barycentric))
+    }
+
+    fn triangle_record(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t: f32,
+        barycentric: [f32; 3],
+    ) -> HitRecord<'_> {
+        let [a, b, c] = self.indices[index];
+        let [a, b, c] = [a as usize, b as usize, c as usize];
+        let [w0, w1, w2] = barycentric;
+
+        let [p0, p1, p2] = self.corners(index);
+        let geometric = cross(&(p1 - p0), &(p2 - p0));
+        let normal = if self.normals.is_empty() {
+            geometric
+        } else {
+            (&self.normals[a] * w0) + (&self.normals[b] * w1) + (&self.normals[c] * w2)
+        };
+        // The geometric normal decides the side, as interpolated normals can
+        // lean past the ray near silhouettes.
+        let normal = if !self.closed && dot(&geometric, &ray.direction) > 0.0 {
+            &normal * -1.0
+        } else {
+            normal
+        };
+        let (u, v) = if self.uvs.is_empty() {
+            (w1, w2)
+        } else {
+            (
+                self.uvs[a][0] * w0 + self.uvs[b][0] * w1 + self.uvs[c][0] * w2,
+                self.uvs[a][1] * w0 + self.uvs[b][1] * w1 + self.uvs[c][1] * w2,
+            )
+        };
+
+        let colour = if self.colours.is_empty() {
+            Vec3::from(1.0, 1.0, 1.0)
+        } else {
+            (&self.colours[a] * w0) + (&self.colours[b] * w1) + (&self.colours[c] * w2)
+        };
+
+        HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: normal.make_normalised(),
+            u,
+            v,
+            colour,
+            material: &*self.material,
+        }
+    }
+
+    fn triangle_box(&self, index: usize) -> Aabb {
+        let [p0, p1, p2] = self.corners(index);
+        Aabb::build(p0.min(p1).min(p2), p0.max(p1).max(p2)).padded(MIN_BOX_EXTENT)
+    }
+}
+
+// True if every edge is shared by exactly two triangles. Corners are
+// compared by position, as loaders split vertices along seams in the normals
+// or texture coordinates.
+fn is_closed(positions: &[Vec3], indices: &[[u32; 3]]) -> bool {
+    let mut corners = HashMap::new();
+    let corner_ids: Vec<usize> = positions
+        .iter()
+        .map(|p| {
+            let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+            let next = corners.len();
+            *corners.entry(key).or_insert(next)
+        })
+        .collect();
+
+    let mut edges = HashMap::new();
+    for triangle in indices {
+        let [a, b, c] = triangle.map(|index| corner_ids[index as usize]);
+        for (from, to) in [(a, b), (b, c), (c, a)] {
+            *edges.entry((from.min(to), from.max(to))).or_insert(0) += 1;
+        }
+    }
+    !edges.is_empty() && edges.values().all(|&count| count == 2)
+}
+
+fn check_count(attribute: &'static str, expected: usize, found: usize) -> Result<(), MeshError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(MeshError::AttributeCount {
+            attribute,
+            expected,
+            found,
+        })
+    }
+}
+
+// Tests every triangle in turn; fine for a handful of triangles, larger
+// meshes should be split with `into_triangles` so the BVH can cull them.
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut t_max = t_max;
+        for index in 0..self.indices.len() {
+            if let Some(record) = self.hit_triangle(index, ray, t_min, t_max) {
+                t_max = record.t;
+                closest = Some(record);
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (0..self.indices.len())
+            .map(|index| self.triangle_box(index))
+            .reduce(|left, right| Aabb::surrounding_box(&left, &right))
+            .unwrap_or_else(|| Aabb::build(Vec3::default(), Vec3::default()))
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.mesh.hit_triangle(self.index, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.mesh.triangle_box(self.index)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: i32,
+        t_min: f32,
+        t_max: &[f32; PACKET_SIZE],
+    ) -> [Option<HitRecord<'_>>; PACKET_SIZE] {
+        let (hits, t, barycentric) =
+            intersect_triangle_packet(packet, active, self.mesh.corners(self.index), t_min, t_max);
+        let mut records = packet::no_hits();
+        for lane in packet::lanes(hits) {
+            records[lane] = Some(self.mesh.triangle_record(
+                self.index,
+                &packet.rays[lane],
+                t[lane],
+                barycentric[lane],
+            ));
+        }
+        records
+    }
+}
+
+// Watertight ray/triangle test (Woop, Benthin and Wald, "Watertight
+// Ray/Triangle Intersection", JCGT 2013). The triangle is sheared into a
+// space where the ray runs along +z from the origin, so edges shared by two
+// triangles are evaluated identically and rays cannot slip between them.
+// Returns the distance and the barycentric weights of the three corners.
+pub fn intersect_triangle(
+    ray: &Ray,
+    corners: [&Vec3; 3],
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, [f32; 3])> {
+    let ([kx, ky, kz], [shear_x, shear_y, shear_z]) = triangle_shear(&ray.direction)?;
+
+    let a = corners[0] - &ray.origin;
+    let b = corners[1] - &ray.origin;
+    let c = corners[2] - &ray.origin;
+    let ax = *a.get(kx) - shear_x * *a.get(kz);
+    let ay = *a.get(ky) - shear_y * *a.get(kz);
+    let bx = *b.get(kx) - shear_x * *b.get(kz);
+    let by = *b.get(ky) - shear_y * *b.get(kz);
+    let cx = *c.get(kx) - shear_x * *c.get(kz);
+    let cy = *c.get(ky) - shear_y * *c.get(kz);
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+    // Exactly zero means the ray grazes an edge; redo those in double
+    // precision so the decision is the same for both triangles sharing it.
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        let (ax, ay, bx, by, cx, cy) = (
+            f64::from(ax),
+            f64::from(ay),
+            f64::from(bx),
+            f64::from(by),
+            f64::from(cx),
+            f64::from(cy),
+        );
+        u = (cx * by - cy * bx) as f32;
+        v = (ax * cy - ay * cx) as f32;
+        w = (bx * ay - by * ax) as f32;
+    }
+
+    // Both windings are accepted, so all edge functions need the same sign.
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let determinant = u + v + w;
+    if determinant == 0.0 {
+        return None;
+    }
+
+    let az = shear_z * *a.get(kz);
+    let bz = shear_z * *b.get(kz);
+    let cz = shear_z * *c.get(kz);
+    let t = (u * az + v * bz + w * cz) / determinant;
+    if !(t > t_min && t < t_max) {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    Some((t, [u * inverse, v * inverse, w * inverse]))
+}
+
+// Which axes of a ray direction the watertight test treats as x, y and z,
+// the longest becoming z, and the shear that turns the ray along +z. None
+// for a zero direction, which hits nothing.
+pub(crate) fn triangle_shear(direction: &Vec3) -> Option<([usize; 3], [f32; 3])> {
+    let abs = [
+        direction.x().abs(),
+        direction.y().abs(),
+        direction.z().abs(),
+    ];
+    let kz = if abs[0] > abs[1] {
+        if abs[0] > abs[2] {
+            0
+        } else {
+            2
+        }
+    } else if abs[1] > abs[2] {
+        1
+    } else {
+        2
+    };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    // Keep the winding of the triangle when looking down -z.
+    if *direction.get(kz) < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let dz = *direction.get(kz);
+    if dz == 0.0 {
+        return None;
+    }
+    Some((
+        [kx, ky, kz],
+        [*direction.get(kx) / dz, *direction.get(ky) / dz, 1.0 / dz],
+    ))
+}
+
+// `intersect_triangle` for the rays of a packet set in `active`, returning
+// the lanes that hit, their distances and barycentric weights. The sheared
+// corners and edge functions are worked out in the same order as for a
+// single ray; lanes grazing an edge take the single-ray path, which
+// settles those in double precision.
+fn intersect_triangle_packet(
+    packet: &RayPacket,
+    active: i32,
+    corners: [&Vec3; 3],
+    t_min: f32,
+    t_max: &[f32; PACKET_SIZE],
+) -> (i32, [f32; PACKET_SIZE], [[f32; 3]; PACKET_SIZE]) {
+    let mut t = [0.0; PACKET_SIZE];
+    let mut barycentric = [[0.0; 3]; PACKET_SIZE];
+    let active = active & packet.shear_valid;
+    if active == 0 {
+        return (0, t, barycentric);
+    }
+
+    let (hits, grazing) = unsafe {
+        // Corner minus origin, with the axes of each lane put in kx, ky, kz
+        // order.
+        let permuted = |corner: &Vec3| {
+            let relative = [0, 1, 2]
+                .map(|axis| _mm_sub_ps(_mm_set1_ps(*corner.get(axis)), packet.origin[axis]));
+            packet.permutation.map(|select| {
+                _mm_or_ps(
+                    _mm_and_ps(select[0], relative[0]),
+                    _mm_or_ps(
+                        _mm_and_ps(select[1], relative[1]),
+                        _mm_and_ps(select[2], relative[2]),
+                    ),
+                )
+            })
+        };
+        let [a, b, c] = corners.map(permuted);
+        let sheared = |p: &[__m128; 3], axis: usize| {
+            _mm_sub_ps(p[axis], _mm_mul_ps(packet.shear[axis], p[2]))
+        };
+        let (ax, ay) = (sheared(&a, 0), sheared(&a, 1));
+        let (bx, by) = (sheared(&b, 0), sheared(&b, 1));
+        let (cx, cy) = (sheared(&c, 0), sheared(&c, 1));
+
+        let u = _mm_sub_ps(_mm_mul_ps(cx, by), _mm_mul_ps(cy, bx));
+        let v = _mm_sub_ps(_mm_mul_ps(ax, cy), _mm_mul_ps(ay, cx));
+        let w = _mm_sub_ps(_mm_mul_ps(bx, ay), _mm_mul_ps(by, ax));
+        let zero = _mm_setzero_ps();
+        let any = |compare: unsafe fn(__m128, __m128) -> __m128| {
+            _mm_or_ps(
+                compare(u, zero),
+                _mm_or_ps(compare(v, zero), compare(w, zero)),
+            )
+        };
+        let grazing = _mm_movemask_ps(any(_mm_cmpeq_ps)) & active;
+
+        let mixed_signs = _mm_and_ps(any(_mm_cmplt_ps), any(_mm_cmpgt_ps));
+        let determinant = _mm_add_ps(_mm_add_ps(u, v), w);
+        let az = _mm_mul_ps(packet.shear[2], a[2]);
+        let bz = _mm_mul_ps(packet.shear[2], b[2]);
+        let cz = _mm_mul_ps(packet.shear[2], c[2]);
+        let distance = _mm_div_ps(
+            _mm_add_ps(
+                _mm_add_ps(_mm_mul_ps(u, az), _mm_mul_ps(v, bz)),
+                _mm_mul_ps(w, cz),
+            ),
+            determinant,
+        );
+        let within = _mm_and_ps(
+            _mm_cmpgt_ps(distance, _mm_set1_ps(t_min)),
+            _mm_cmplt_ps(distance, _mm_loadu_ps(t_max.as_ptr())),
+        );
+        let hits = _mm_andnot_ps(
+            mixed_signs,
+            _mm_and_ps(_mm_cmpneq_ps(determinant, zero), within),
+        );
+        let hits = _mm_movemask_ps(hits) & active & !grazing;
+
+        let mut lanes = [[0.0; PACKET_SIZE]; 4];
+        for (lane, value) in lanes.iter_mut().zip([distance, u, v, w]) {
+            _mm_storeu_ps(lane.as_mut_ptr(), value);
+        }
+        for lane in packet::lanes(hits) {
+            t[lane] = lanes[0][lane];
+            let inverse = 1.0 / (lanes[1][lane] + lanes[2][lane] + lanes[3][lane]);
+            barycentric[lane] = [
+                lanes[1][lane] * inverse,
+                lanes[2][lane] * inverse,
+                lanes[3][lane] * inverse,
+            ];
+        }
+        (hits, grazing)
+    };
+
+    let mut hits = hits;
+    for lane in packet::lanes(grazing) {
+        if let Some((distance, weights)) =
+            intersect_triangle(&packet.rays[lane], corners, t_min, t_max[lane])
+        {
+            hits |= 1 << lane;
+            t[lane] = distance;
+            barycentric[lane] = weights;
+        }
+    }
+    (hits, t, barycentric)
+}
+
+// Splits a planar polygon into triangles by ear clipping, returning corner
+// indices. The polygon is projected onto the plane its normal points at
+// most, which keeps its shape. Degenerate leftovers are fanned.
+pub(crate) fn triangulate(polygon: &[Vec3]) -> Vec<[usize; 3]> {
+    if polygon.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method gives a normal that is robust for concave polygons.
+    let mut normal = Vec3::default();
+    for (index, current) in polygon.iter().enumerate() {
+        let next = &polygon[(index + 1) % polygon.len()];
+        normal += &Vec3::from(
+            (current.y() - next.y()) * (current.z() + next.z()),
+            (current.z() - next.z()) * (current.x() + next.x()),
+            (current.x() - next.x()) * (current.y() + next.y()),
+        );
+    }
+    let drop_axis = (0..3)
+        .max_by(|&a, &b| normal.get(a).abs().total_cmp(&normal.get(b).abs()))
+        .unwrap_or(2);
+    let (u_axis, v_axis) = ((drop_axis + 1) % 3, (drop_axis + 2) % 3);
+    let points: Vec<(f32, f32)> = polygon
+        .iter()
+        .map(|point| (*point.get(u_axis), *point.get(v_axis)))
+        .collect();
+    let orientation = if *normal.get(drop_axis) >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    };
+
+    let signed_area = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| {
+        ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)) * orientation
+    };
+
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            );
+            if signed_area(points[a], points[b], points[c]) <= 0.0 {
+                return false;
+            }
+            // No other corner may lie inside the ear.
+            remaining.iter().all(|&other| {
+                other == a
+                    || other == b
+                    || other == c
+                    || signed_area(points[a], points[b], points[other]) < 0.0
+                    || signed_area(points[b], points[c], points[other]) < 0.0
+                    || signed_area(points[c], points[a], points[other]) < 0.0
+            })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + count - 1) % count],
+                    remaining[i],
+                    remaining[(i + 1) % count],
+                ]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
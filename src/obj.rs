@@ -0,0 +1,752 @@
+// Wavefront OBJ and MTL import.
+//
+// Supported OBJ statements are v, vt, vn, f (any number of corners, with
+// v, v/vt, v//vn or v/vt/vn references, negative indices counting back from
+// the latest element), g, o, usemtl and mtllib. Polygons are triangulated by
+// ear clipping, so concave faces come out right. Statements that do not
+// affect the surface, such as s, l, p or the free-form geometry ones, are
+// skipped.
+//
+// Faces are collected into one mesh per group and material. MTL materials
+// are mapped onto the renderer's materials:
+//     Ke other than black              -> DiffuseLight with emission Ke
+//     d below 1, or illum 4, 6, 7, 9   -> Dielectric with refraction index Ni
+//     illum 3, or Ks brighter than Kd  -> Metal with albedo Ks
+//     anything else                    -> Lambertian with albedo Kd
+
+use crate::hitable::Hitable;
+use crate::material::*;
+use crate::mesh::{triangulate, TriangleMesh};
+use crate::vec3::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}: line {}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub emission: Vec3,
+    pub refraction_index: f32,
+    pub dissolve: f32,
+    pub illumination_model: Option<u32>,
+}
+
+// The faces of one group drawn with one material, with vertices already
+// de-duplicated. Normals and texture coordinates are either present for
+// every vertex or empty.
+pub struct ObjMesh {
+    pub group: String,
+    pub material: Option<String>,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: HashMap<String, ObjMaterial>,
+    // The MTL files the OBJ file references, including any that could not
+    // be read.
+    pub material_libraries: Vec<PathBuf>,
+    // Problems that did not stop the model from loading, such as a missing
+    // MTL file, for the caller to report.
+    pub warnings: Vec<ObjError>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        ObjMaterial {
+            name: String::new(),
+            diffuse: Vec3::from(0.8, 0.8, 0.8),
+            specular: Vec3::default(),
+            emission: Vec3::default(),
+            refraction_index: 1.5,
+            dissolve: 1.0,
+            illumination_model: None,
+        }
+    }
+}
+
+impl ObjMaterial {
+    pub fn build(&self) -> Box<dyn Material> {
+        let transparent = self.dissolve < 1.0
+            || matches!(
+                self.illumination_model,
+                Some(4) | Some(6) | Some(7) | Some(9)
+            );
+        if self.emission.max_elem() > 0.0 {
+            Box::new(DiffuseLight::with_emission(self.emission))
+        } else if transparent {
+            Box::new(Dielectric::with_refraction_index(self.refraction_index))
+        } else if self.illumination_model == Some(3)
+            || self.specular.max_elem() > self.diffuse.max_elem()
+        {
+            Box::new(Metal::with_albedo(self.specular))
+        } else {
+            Box::new(Lambertian::with_albedo(self.diffuse))
+        }
+    }
+}
+
+impl ObjModel {
+    // Builds one triangle mesh per group and material. Faces without a
+    // usemtl, or with a material the MTL files do not define, get the
+    // default grey Lambertian. `material` replaces all MTL materials.
+    pub fn into_meshes(
+        self,
+        material: Option<&dyn Fn() -> Box<dyn Material>>,
+    ) -> Vec<TriangleMesh> {
+        let materials = self.materials;
+        self.meshes
+            .into_iter()
+            .map(|mesh| {
+                let built = match material {
+                    Some(material) => material(),
+                    None => mesh
+                        .material
+                        .as_ref()
+                        .and_then(|name| materials.get(name))
+                        .cloned()
+                        .unwrap_or_default()
+                        .build(),
+                };
+                let has_normals = !mesh.normals.is_empty();
+                let has_uvs = !mesh.uvs.is_empty();
+                // The parser only emits in-range indices and complete
+                // attribute buffers, so these cannot fail.
+                let mut triangles = TriangleMesh::new(mesh.positions, mesh.indices, built)
+                    .expect("OBJ face indices are checked while parsing");
+                if has_normals {
+                    triangles = triangles
+                        .with_normals(mesh.normals)
+                        .expect("one normal per vertex");
+                }
+                if has_uvs {
+                    triangles = triangles.with_uvs(mesh.uvs).expect("one uv per vertex");
+                }
+                triangles
+            })
+            .collect()
+    }
+
+    // Every triangle of every mesh, ready to be added to a scene.
+    pub fn into_hitables(
+        self,
+        material: Option<&dyn Fn() -> Box<dyn Material>>,
+    ) -> Vec<Box<dyn Hitable>> {
+        self.into_meshes(material)
+            .into_iter()
+            .flat_map(TriangleMesh::into_triangles)
+            .collect()
+    }
+}
+
+// Loads an OBJ file along with the MTL libraries it references, which are
+// looked up relative to the OBJ file.
+pub fn load_obj(path: &Path) -> Result<ObjModel, ObjError> {
+    let source = read_file(path)?;
+    let mut parser = ObjParser {
+        path: path.to_path_buf(),
+        line: 0,
+        positions: Vec::new(),
+        uvs: Vec::new(),
+        normals: Vec::new(),
+        materials: HashMap::new(),
+        material_libraries: Vec::new(),
+        warnings: Vec::new(),
+        group: "default".to_string(),
+        material: None,
+        meshes: Vec::new(),
+        mesh_lookup: HashMap::new(),
+    };
+    for (index, line) in source.lines().enumerate() {
+        parser.line = index + 1;
+        parser.parse_line(line)?;
+    }
+
+    let meshes = parser
+        .meshes
+        .into_iter()
+        .filter(|mesh| !mesh.indices.is_empty())
+        .map(MeshBuilder::finish)
+        .collect();
+    Ok(ObjModel {
+        meshes,
+        materials: parser.materials,
+        material_libraries: parser.material_libraries,
+        warnings: parser.warnings,
+    })
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    let source = read_file(path)?;
+    let mut materials = HashMap::new();
+    let mut current: Option<ObjMaterial> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+        let mut words = strip_comment(line).split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        if keyword == "newmtl" {
+            if arguments.is_empty() {
+                return Err(error("newmtl needs a material name".to_string()));
+            }
+            if let Some(material) = current.take() {
+                materials.insert(material.name.clone(), material);
+            }
+            current = Some(ObjMaterial {
+                name: arguments.join(" "),
+                ..ObjMaterial::default()
+            });
+            continue;
+        }
+
+        let material = match (&mut current, keyword) {
+            (Some(material), _) => material,
+            // Texture maps and the like are not supported, so anything but
+            // the properties below is ignored wherever it appears.
+            (None, "Kd" | "Ks" | "Ke" | "Ni" | "d" | "Tr" | "illum") => {
+                return Err(error(format!("{} appears before any newmtl", keyword)))
+            }
+            (None, _) => continue,
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_colour(keyword, &arguments).map_err(error)?,
+            "Ks" => material.specular = parse_colour(keyword, &arguments).map_err(error)?,
+            "Ke" => material.emission = parse_colour(keyword, &arguments).map_err(error)?,
+            "Ni" => material.refraction_index = parse_single(keyword, &arguments).map_err(error)?,
+            "d" => material.dissolve = parse_single(keyword, &arguments).map_err(error)?,
+            // Transparency, the inverse of dissolve.
+            "Tr" => {
+                material.dissolve = 1.0 - parse_single::<f32>(keyword, &arguments).map_err(error)?
+            }
+            "illum" => {
+                let model = parse_single::<u32>(keyword, &arguments).map_err(error)?;
+                material.illumination_model = Some(model);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(material) = current {
+        materials.insert(material.name.clone(), material);
+    }
+    Ok(materials)
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    let bytes = fs::read(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    // Exporters are not consistent about encodings; names are the only text
+    // that matters, so anything that is not UTF-8 is replaced.
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn parse_number(keyword: &str, text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!(
+            "invalid number '{}' in {} statement",
+            text, keyword
+        )),
+    }
+}
+
+fn parse_single<T: std::str::FromStr>(keyword: &str, arguments: &[&str]) -> Result<T, String> {
+    match arguments {
+        [value] => value
+            .parse()
+            .map_err(|_| format!("invalid value '{}' for {}", value, keyword)),
+        _ => Err(format!(
+            "{} expects one value, found {}",
+            keyword,
+            arguments.len()
+        )),
+    }
+}
+
+fn parse_colour(keyword: &str, arguments: &[&str]) -> Result<Vec3, String> {
+    match arguments {
+        // A single value is a grey.
+        [grey] => {
+            let grey = parse_number(keyword, grey)?;
+            Ok(Vec3::from(grey, grey, grey))
+        }
+        [r, g, b] => Ok(Vec3::from(
+            parse_number(keyword, r)?,
+            parse_number(keyword, g)?,
+            parse_number(keyword, b)?,
+        )),
+        // Spectral and CIE XYZ colours are not supported.
+        ["spectral", ..] | ["xyz", ..] => Err(format!(
+            "{} {} colours are not supported",
+            keyword, arguments[0]
+        )),
+        _ => Err(format!(
+            "{} expects three colour components, found {}",
+            keyword,
+            arguments.len()
+        )),
+    }
+}
+
+// One face corner: indices into the position, uv and normal lists.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct MeshBuilder {
+    group: String,
+    material: Option<String>,
+    vertex_lookup: HashMap<Corner, u32>,
+    positions: Vec<Vec3>,
+    uvs: Vec<Option<[f32; 2]>>,
+    normals: Vec<Option<Vec3>>,
+    indices: Vec<[u32; 3]>,
+}
+
+impl MeshBuilder {
+    // Attributes only some vertices have are dropped altogether, since a
+    // mesh either has them for every vertex or not at all.
+    fn finish(self) -> ObjMesh {
+        let normals = if self.normals.iter().all(Option::is_some) {
+            self.normals.into_iter().flatten().collect()
+        } else {
+            Vec::new()
+        };
+        let uvs = if self.uvs.iter().all(Option::is_some) {
+            self.uvs.into_iter().flatten().collect()
+        } else {
+            Vec::new()
+        };
+        ObjMesh {
+            group: self.group,
+            material: self.material,
+            positions: self.positions,
+            normals,
+            uvs,
+            indices: self.indices,
+        }
+    }
+}
+
+struct ObjParser {
+    path: PathBuf,
+    line: usize,
+    positions: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<Vec3>,
+    materials: HashMap<String, ObjMaterial>,
+    material_libraries: Vec<PathBuf>,
+    warnings: Vec<ObjError>,
+    group: String,
+    material: Option<String>,
+    meshes: Vec<MeshBuilder>,
+    mesh_lookup: HashMap<(String, Option<String>), usize>,
+}
+
+impl ObjParser {
+    fn error<T>(&self, message: String) -> Result<T, ObjError> {
+        Err(ObjError::Parse {
+            path: self.path.clone(),
+            line: self.line,
+            message,
+        })
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ObjError> {
+        let mut words = strip_comment(line).split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        match keyword {
+            "v" => {
+                // A fourth (w) component or trailing vertex colours are ignored.
+                let position = self.vector(keyword, &arguments, 3)?;
+                self.positions
+                    .push(Vec3::from(position[0], position[1], position[2]));
+            }
+            "vt" => {
+                let uv = self.vector(keyword, &arguments, 1)?;
+                self.uvs.push([uv[0], uv.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let normal = self.vector(keyword, &arguments, 3)?;
+                self.normals
+                    .push(Vec3::from(normal[0], normal[1], normal[2]));
+            }
+            "f" => self.parse_face(&arguments)?,
+            "g" | "o" => {
+                self.group = if arguments.is_empty() {
+                    "default".to_string()
+                } else {
+                    arguments.join(" ")
+                };
+            }
+            "usemtl" => {
+                if arguments.is_empty() {
+                    return self.error("usemtl needs a material name".to_string());
+                }
+                self.material = Some(arguments.join(" "));
+            }
+            "mtllib" => {
+                if arguments.is_empty() {
+                    return self.error("mtllib needs a file name".to_string());
+                }
+                let directory = self.path.parent().unwrap_or_else(|| Path::new(""));
+                for name in arguments {
+                    let library_path = directory.join(name);
+                    self.material_libraries.push(library_path.clone());
+                    match load_mtl(&library_path) {
+                        Ok(library) => self.materials.extend(library),
+                        // Models are often passed around without their MTL
+                        // files; their faces get the default material.
+                        Err(err @ ObjError::Io { .. }) => self.warnings.push(ObjError::Parse {
+                            path: self.path.clone(),
+                            line: self.line,
+                            message: format!("{}; using the default material", err),
+                        }),
+                        Err(err) => {
+                            return self.error(format!("cannot load material library: {}", err))
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Reads at least `minimum` numbers; any beyond the first three are
+    // ignored.
+    fn vector(
+        &self,
+        keyword: &str,
+        arguments: &[&str],
+        minimum: usize,
+    ) -> Result<Vec<f32>, ObjError> {
+        if arguments.len() < minimum {
+            return self.error(format!(
+                "{} expects at least {} values, found {}",
+                keyword,
+                minimum,
+                arguments.len()
+            ));
+        }
+        arguments
+            .iter()
+            .take(3)
+            .map(|text| parse_number(keyword, text).or_else(|message| self.error(message)))
+            .collect()
+    }
+
+    fn parse_face(&mut self, arguments: &[&str]) -> Result<(), ObjError> {
+        if arguments.len() < 3 {
+            return self.error(format!(
+                "a face needs at least three vertices, found {}",
+                arguments.len()
+            ));
+        }
+
+        let mut corners = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            corners.push(self.parse_corner(argument)?);
+        }
+
+        let key = (self.group.clone(), self.material.clone());
+        let mesh_index = match self.mesh_lookup.get(&key) {
+            Some(&index) => index,
+            None => {
+                self.meshes.push(MeshBuilder {
+                    group: key.0.clone(),
+                    material: key.1.clone(),
+                    vertex_lookup: HashMap::new(),
+                    positions: Vec::new(),
+                    uvs: Vec::new(),
+                    normals: Vec::new(),
+                    indices: Vec::new(),
+                });
+                self.mesh_lookup.insert(key, self.meshes.len() - 1);
+                self.meshes.len() - 1
+            }
+        };
+
+        let polygon: Vec<Vec3> = corners
+            .iter()
+            .map(|corner| self.positions[corner.0])
+            .collect();
+        let triangles = triangulate(&polygon);
+
+        let (positions, uvs, normals) = (&self.positions, &self.uvs, &self.normals);
+        let mesh = &mut self.meshes[mesh_index];
+        let mut vertices = Vec::with_capacity(corners.len());
+        for corner in &corners {
+            let next_index = mesh.positions.len() as u32;
+            let index = *mesh.vertex_lookup.entry(*corner).or_insert(next_index);
+            if index == next_index {
+                mesh.positions.push(positions[corner.0]);
+                mesh.uvs.push(corner.1.map(|index| uvs[index]));
+                mesh.normals.push(corner.2.map(|index| normals[index]));
+            }
+            vertices.push(index);
+        }
+        for [a, b, c] in triangles {
+            mesh.indices.push([vertices[a], vertices[b], vertices[c]]);
+        }
+        Ok(())
+    }
+
+    fn parse_corner(&self, text: &str) -> Result<Corner, ObjError> {
+        let mut parts = text.split('/');
+        let position = parts.next().unwrap_or("");
+        let uv = parts.next().filter(|part| !part.is_empty());
+        let normal = parts.next().filter(|part| !part.is_empty());
+        if parts.next().is_some() || position.is_empty() {
+            return self.error(format!("malformed face vertex '{}'", text));
+        }
+
+        let position = self.resolve(position, ("vertex", "vertices"), self.positions.len())?;
+        let uv = match uv {
+            Some(uv) => Some(self.resolve(
+                uv,
+                ("texture coordinate", "texture coordinates"),
+                self.uvs.len(),
+            )?),
+            None => None,
+        };
+        let normal = match normal {
+            Some(normal) => {
+                Some(self.resolve(normal, ("normal", "normals"), self.normals.len())?)
+            }
+            None => None,
+        };
+        Ok((position, uv, normal))
+    }
+
+    // OBJ indices start at 1; negative ones count back from the last element
+    // defined so far.
+    fn resolve(&self, text: &str, names: (&str, &str), count: usize) -> Result<usize, ObjError> {
+        let index: i64 = match text.parse() {
+            Ok(index) => index,
+            Err(_) => return self.error(format!("invalid {} index '{}'", names.0, text)),
+        };
+        let resolved = if index > 0 {
+            index - 1
+        } else {
+            count as i64 + index
+        };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return self.error(format!(
+                "{} index {} is out of range, {} {} are defined at this point",
+                names.0, index, count, names.1
+            ));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `files` into a fresh directory and loads the first as OBJ.
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<ObjModel, ObjError> {
+        let directory =
+            std::env::temp_dir().join(format!("raytracer-obj-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            fs::write(directory.join(file), contents).unwrap();
+        }
+        let model = load_obj(&directory.join(files[0].0));
+        fs::remove_dir_all(&directory).unwrap();
+        model
+    }
+
+    fn parse_error(result: Result<ObjModel, ObjError>) -> (usize, String) {
+        match result {
+            Err(ObjError::Parse { line, message, .. }) => (line, message),
+            Err(err) => panic!("expected a parse error, got {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    fn xyz(v: &Vec3) -> [f32; 3] {
+        [*v.x(), *v.y(), *v.z()]
+    }
+
+    #[test]
+    fn parses_faces_groups_and_materials() {
+        let model = load(
+            "faces",
+            &[
+                (
+                    "model.obj",
+                    "mtllib model.mtl\n\
+                     v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                     vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                     vn 0 0 1\n\
+                     g front\nusemtl red\n\
+                     f 1/1/1 2/2/1 3/3/1 4/4/1  # a quad\n\
+                     g back\nusemtl glass\n\
+                     f -1 -2 -3\n",
+                ),
+                (
+                    "model.mtl",
+                    "newmtl red\nKd 0.8 0.1 0.1\n\
+                     newmtl glass\nKd 1\nd 0.5\nNi 1.33\n",
+                ),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(model.meshes.len(), 2);
+        let front = &model.meshes[0];
+        assert_eq!(front.group, "front");
+        assert_eq!(front.material.as_deref(), Some("red"));
+        assert_eq!(front.positions.len(), 4);
+        assert_eq!(front.indices.len(), 2);
+        assert_eq!(front.normals.len(), 4);
+        assert_eq!(front.uvs[2], [1.0, 1.0]);
+
+        // Negative indices count back from the last vertex; without uvs or
+        // normals the mesh has none.
+        let back = &model.meshes[1];
+        assert_eq!(back.group, "back");
+        let corners: Vec<[f32; 3]> = back.indices[0]
+            .iter()
+            .map(|&index| xyz(&back.positions[index as usize]))
+            .collect();
+        assert_eq!(corners, [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]]);
+        assert!(back.normals.is_empty() && back.uvs.is_empty());
+
+        assert_eq!(xyz(&model.materials["red"].diffuse), [0.8, 0.1, 0.1]);
+        let glass = &model.materials["glass"];
+        assert_eq!(xyz(&glass.diffuse), [1.0, 1.0, 1.0]);
+        assert_eq!((glass.dissolve, glass.refraction_index), (0.5, 1.33));
+        assert_eq!(model.material_libraries.len(), 1);
+    }
+
+    #[test]
+    fn missing_material_library_falls_back_to_the_default() {
+        let model = load(
+            "missing-mtl",
+            &[(
+                "model.obj",
+                "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n",
+            )],
+        )
+        .unwrap();
+        assert!(model.materials.is_empty());
+        assert_eq!(model.meshes[0].material.as_deref(), Some("red"));
+        assert!(model.material_libraries[0].ends_with("missing.mtl"));
+        assert_eq!(model.warnings.len(), 1);
+        let warning = model.warnings[0].to_string();
+        assert!(warning.contains("line 1: ") && warning.contains("missing.mtl"));
+        assert_eq!(model.into_hitables(None).len(), 1);
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        let cases = [
+            ("f 1 2 4\n", "vertex index 4 is out of range"),
+            ("f 0 1 2\n", "vertex index 0 is out of range"),
+            ("f 1/1 2 3\n", "texture coordinate index 1 is out of range"),
+            ("f 1 2\n", "at least three vertices"),
+            ("f 1/2/3/4 2 3\n", "malformed face vertex"),
+            ("f a 2 3\n", "invalid vertex index 'a'"),
+            ("v 1 nan 0\n", "invalid number 'nan'"),
+            ("vn 1 0\n", "at least 3 values"),
+            ("usemtl\n", "usemtl needs a material name"),
+        ];
+        for (statement, expected) in cases.iter() {
+            let source = format!("{}{}", triangle, statement);
+            let (line, message) = parse_error(load("errors", &[("model.obj", &source)]));
+            assert_eq!(line, 4, "{}", statement);
+            assert!(message.contains(expected), "{}: {}", statement, message);
+        }
+    }
+
+    #[test]
+    fn reports_errors_in_material_libraries() {
+        let (line, message) = parse_error(load(
+            "bad-mtl",
+            &[
+                ("model.obj", "# materials\nmtllib model.mtl\n"),
+                ("model.mtl", "newmtl red\nKd 1 0\n"),
+            ],
+        ));
+        assert_eq!(line, 2);
+        assert!(message.contains("line 2: Kd expects three colour components"));
+
+        let (_, message) = parse_error(load(
+            "orphan-mtl",
+            &[("model.obj", "mtllib a.mtl\n"), ("a.mtl", "Kd 1 1 1\n")],
+        ));
+        assert!(
+            message.contains("Kd appears before any newmtl"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn missing_model_is_an_io_error() {
+        let path = std::env::temp_dir().join("raytracer-obj-does-not-exist.obj");
+        assert!(matches!(load_obj(&path), Err(ObjError::Io { .. })));
+    }
+}
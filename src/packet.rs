@@ -0,0 +1,148 @@
+// Rays traced together as one packet. Neighbouring camera rays pass through
+// nearly the same boxes, so walking the BVH once for all of them and testing
+// each box and shape against the whole packet with SSE shares most of the
+// work. The rays are also kept one component per register, lane i holding
+// ray i; masks have bit i set for lane i, as `_mm_movemask_ps` returns them.
+//
+// Only camera rays are traced this way. The renderer has no shadow rays to
+// batch: it sends no rays toward the lights, which are only found by paths
+// that bounce into them. Bounced rays scatter in all directions and share
+// too few boxes for packets to pay off.
+
+use crate::hitable::Hitable;
+use crate::mesh::triangle_shear;
+use crate::ray::*;
+
+use std::arch::x86_64::*;
+use std::sync::OnceLock;
+
+pub const PACKET_SIZE: usize = 4;
+
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    pub(crate) origin: [__m128; 3],
+    pub(crate) direction: [__m128; 3],
+    pub(crate) inv_direction: [__m128; 3],
+    // The watertight triangle test's axis permutation and shear per lane:
+    // `permutation[k][axis]` selects the lanes whose k-th axis is `axis`.
+    pub(crate) permutation: [[__m128; 3]; 3],
+    pub(crate) shear: [__m128; 3],
+    // Lanes whose rays can hit a triangle at all.
+    pub(crate) shear_valid: i32,
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; PACKET_SIZE]) -> Self {
+        let lanes = |value: &dyn Fn(&Ray) -> f32| unsafe {
+            _mm_setr_ps(
+                value(&rays[0]),
+                value(&rays[1]),
+                value(&rays[2]),
+                value(&rays[3]),
+            )
+        };
+        let origin = [0, 1, 2].map(|axis| lanes(&|ray| *ray.origin.get(axis)));
+        let direction = [0, 1, 2].map(|axis| lanes(&|ray| *ray.direction.get(axis)));
+        let inv_direction = direction.map(|d| unsafe { _mm_div_ps(_mm_set1_ps(1.0), d) });
+
+        let shears = rays.each_ref().map(|ray| triangle_shear(&ray.direction));
+        let shear_valid = (0..PACKET_SIZE)
+            .filter(|&lane| shears[lane].is_some())
+            .fold(0, |mask, lane| mask | 1 << lane);
+        let (axes, shear) = (
+            shears.map(|shear| shear.map_or([0; 3], |(axes, _)| axes)),
+            shears.map(|shear| shear.map_or([0.0; 3], |(_, shear)| shear)),
+        );
+        let select = |k: usize, axis: usize| {
+            let lane = |lane: usize| f32::from_bits(if axes[lane][k] == axis { !0 } else { 0 });
+            unsafe { _mm_setr_ps(lane(0), lane(1), lane(2), lane(3)) }
+        };
+        let permutation = [0, 1, 2].map(|k| [0, 1, 2].map(|axis| select(k, axis)));
+        let shear = [0, 1, 2]
+            .map(|k| unsafe { _mm_setr_ps(shear[0][k], shear[1][k], shear[2][k], shear[3][k]) });
+
+        RayPacket {
+            rays,
+            origin,
+            direction,
+            inv_direction,
+            permutation,
+            shear,
+            shear_valid,
+        }
+    }
+
+    // Which lanes' rays pass through the box within their own range, all
+    // bits set for those, and where each enters it. The same slab test as
+    // `Aabb::hit`.
+    pub(crate) fn hit_box(
+        &self,
+        min: [f32; 3],
+        max: [f32; 3],
+        t_min: f32,
+        t_max: __m128,
+    ) -> (__m128, __m128) {
+        unsafe {
+            let mut near = [_mm_setzero_ps(); 3];
+            let mut far = [_mm_setzero_ps(); 3];
+            for axis in 0..3 {
+                let t0 = _mm_mul_ps(
+                    _mm_sub_ps(_mm_set1_ps(min[axis]), self.origin[axis]),
+                    self.inv_direction[axis],
+                );
+                let t1 = _mm_mul_ps(
+                    _mm_sub_ps(_mm_set1_ps(max[axis]), self.origin[axis]),
+                    self.inv_direction[axis],
+                );
+                near[axis] = _mm_min_ps(t0, t1);
+                far[axis] = _mm_max_ps(t0, t1);
+            }
+            let near = _mm_max_ps(
+                _mm_set1_ps(t_min),
+                _mm_max_ps(near[0], _mm_max_ps(near[1], near[2])),
+            );
+            let far = _mm_min_ps(t_max, _mm_min_ps(far[0], _mm_min_ps(far[1], far[2])));
+            (_mm_cmpge_ps(far, near), near)
+        }
+    }
+}
+
+// Whether the packet sphere test can match `f32::mul_add` with FMA
+// instructions. Detected once rather than for every packet.
+pub(crate) fn has_fma() -> bool {
+    static FMA: OnceLock<bool> = OnceLock::new();
+    *FMA.get_or_init(|| is_x86_feature_detected!("fma"))
+}
+
+// `mask` with all bits of its lanes set, for selecting lanes of a register.
+pub(crate) fn lane_mask(mask: i32) -> __m128 {
+    unsafe {
+        let bits = _mm_and_si128(_mm_set1_epi32(mask), _mm_setr_epi32(1, 2, 4, 8));
+        _mm_castsi128_ps(_mm_cmpgt_epi32(bits, _mm_setzero_si128()))
+    }
+}
+
+// The lanes set in `mask`, lowest first.
+pub(crate) fn lanes(mask: i32) -> impl Iterator<Item = usize> {
+    (0..PACKET_SIZE).filter(move |lane| mask & (1 << lane) != 0)
+}
+
+// Traces the active lanes one ray at a time, for shapes without a packet
+// test of their own.
+pub(crate) fn hit_each<'a, H: Hitable + ?Sized>(
+    hitable: &'a H,
+    packet: &RayPacket,
+    active: i32,
+    t_min: f32,
+    t_max: &[f32; PACKET_SIZE],
+) -> [Option<HitRecord<'a>>; PACKET_SIZE] {
+    let mut records = no_hits();
+    for lane in lanes(active) {
+        records[lane] = hitable.hit(&packet.rays[lane], t_min, t_max[lane]);
+    }
+    records
+}
+
+pub(crate) fn no_hits<'a>() -> [Option<HitRecord<'a>>; PACKET_SIZE] {
+    std::array::from_fn(|_| None)
+}
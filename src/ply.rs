@@ -0,0 +1,854 @@
+// Stanford PLY import, in the ascii, binary_little_endian and
+// binary_big_endian encodings.
+//
+// Vertices may carry normals (nx ny nz), texture coordinates (u v, s t or
+// texture_u texture_v) and colours (red green blue). Faces are read from the
+// vertex_indices (or vertex_index) list and polygons are triangulated. Other
+// elements and properties are skipped.
+
+use crate::material::*;
+use crate::mesh::{triangulate, TriangleMesh};
+use crate::vec3::Vec3;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    // `location` is a header line or the element being read.
+    Parse {
+        path: PathBuf,
+        location: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            PlyError::Parse {
+                path,
+                location,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), location, message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+// Vertex attributes are either present for every vertex or empty. Colours
+// are linear, ready to be used as an albedo.
+pub struct PlyMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub colours: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl PlyMesh {
+    // Meshes with vertex colours get a white Lambertian so the colours come
+    // through unchanged, others the usual default grey.
+    pub fn default_material(&self) -> Box<dyn Material> {
+        let albedo = if self.colours.is_empty() {
+            Vec3::from(0.8, 0.8, 0.8)
+        } else {
+            Vec3::from(1.0, 1.0, 1.0)
+        };
+        Box::new(Lambertian::with_albedo(albedo))
+    }
+
+    pub fn into_mesh(self, material: Box<dyn Material>) -> TriangleMesh {
+        // Indices are checked against the vertex count and attributes are
+        // complete, so none of these can fail.
+        let mut mesh = TriangleMesh::new(self.positions, self.indices, material)
+            .expect("PLY face indices are checked while loading");
+        if !self.normals.is_empty() {
+            mesh = mesh
+                .with_normals(self.normals)
+                .expect("one normal per vertex");
+        }
+        if !self.uvs.is_empty() {
+            mesh = mesh.with_uvs(self.uvs).expect("one uv per vertex");
+        }
+        if !self.colours.is_empty() {
+            mesh = mesh
+                .with_colours(self.colours)
+                .expect("one colour per vertex");
+        }
+        mesh
+    }
+}
+
+pub fn load_ply(path: &Path) -> Result<PlyMesh, PlyError> {
+    let data = fs::read(path).map_err(|error| PlyError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let error = |location: String, message: String| PlyError::Parse {
+        path: path.to_path_buf(),
+        location,
+        message,
+    };
+
+    let (header, body_start) = parse_header(&data)
+        .map_err(|(line, message)| error(format!("header line {}", line), message))?;
+    let body = &data[body_start..];
+    let mut reader = match header.format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(body).map_err(|_| {
+                error(
+                    "data".to_string(),
+                    "ascii data is not valid text".to_string(),
+                )
+            })?;
+            Reader::Ascii(text)
+        }
+        Format::BinaryLittleEndian => Reader::Binary {
+            data: body,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Reader::Binary {
+            data: body,
+            big_endian: true,
+        },
+    };
+
+    let mut mesh = PlyMesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        colours: Vec::new(),
+        indices: Vec::new(),
+    };
+    for element in &header.elements {
+        // Checked up front so that a corrupt count is reported, rather than
+        // reserving space for it or reading until the data runs out.
+        let remaining = reader.remaining();
+        if element
+            .count
+            .checked_mul(reader.minimum_size(element))
+            .is_none_or(|size| size > remaining)
+        {
+            return Err(error(
+                element.name.clone(),
+                format!(
+                    "{} elements do not fit in the remaining {} bytes of data",
+                    element.count, remaining
+                ),
+            ));
+        }
+        match element.name.as_str() {
+            "vertex" => read_vertices(element, &mut reader, &mut mesh),
+            "face" => read_faces(element, &mut reader, &mut mesh),
+            _ => skip_element(element, &mut reader),
+        }
+        .map_err(|(index, message)| error(format!("{} {}", element.name, index), message))?;
+    }
+
+    let vertex_count = mesh.positions.len();
+    if let Some((face, index)) = mesh.indices.iter().enumerate().find_map(|(face, corners)| {
+        corners
+            .iter()
+            .find(|&&index| index as usize >= vertex_count)
+            .map(|&index| (face, index))
+    }) {
+        return Err(error(
+            "face data".to_string(),
+            format!(
+                "triangle {} uses vertex {} but the file only has {} vertices",
+                face, index, vertex_count
+            ),
+        ));
+    }
+    Ok(mesh)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::UInt8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::UInt16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::UInt32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // Integer colour channels run up to the largest value of their type.
+    fn colour_scale(self) -> f64 {
+        match self {
+            ScalarType::Int8 => 127.0,
+            ScalarType::UInt8 => 255.0,
+            ScalarType::Int16 => 32767.0,
+            ScalarType::UInt16 => 65535.0,
+            ScalarType::Int32 => 2_147_483_647.0,
+            ScalarType::UInt32 => 4_294_967_295.0,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+// Returns the header and the offset of the first data byte, or the failing
+// header line and a message.
+fn parse_header(data: &[u8]) -> Result<(Header, usize), (usize, String)> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let end = match data[offset..].iter().position(|&byte| byte == b'\n') {
+            Some(end) => offset + end,
+            None => return Err((line_number, "the header has no end_header line".to_string())),
+        };
+        let line = String::from_utf8_lossy(&data[offset..end]);
+        offset = end + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        if line_number == 1 {
+            if words != ["ply"] {
+                return Err((line_number, "not a PLY file".to_string()));
+            }
+            continue;
+        }
+
+        match words.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, version] => {
+                if *version != "1.0" {
+                    return Err((line_number, format!("unsupported PLY version {}", version)));
+                }
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err((line_number, format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => {
+                let count = count
+                    .parse()
+                    .map_err(|_| (line_number, format!("invalid element count '{}'", count)))?;
+                elements.push(Element {
+                    name: name.to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            ["property", "list", count, item, name] => {
+                let property = Property {
+                    name: name.to_string(),
+                    kind: PropertyKind::List {
+                        count: scalar_type(count, line_number)?,
+                        item: scalar_type(item, line_number)?,
+                    },
+                };
+                add_property(&mut elements, property, line_number)?;
+            }
+            ["property", kind, name] => {
+                let property = Property {
+                    name: name.to_string(),
+                    kind: PropertyKind::Scalar(scalar_type(kind, line_number)?),
+                };
+                add_property(&mut elements, property, line_number)?;
+            }
+            ["end_header"] => break,
+            _ => {
+                return Err((
+                    line_number,
+                    format!("unexpected header line '{}'", line.trim()),
+                ))
+            }
+        }
+    }
+
+    match format {
+        Some(format) => Ok((Header { format, elements }, offset)),
+        None => Err((
+            line_number,
+            "the header does not declare a format".to_string(),
+        )),
+    }
+}
+
+fn scalar_type(name: &str, line_number: usize) -> Result<ScalarType, (usize, String)> {
+    ScalarType::parse(name)
+        .ok_or_else(|| (line_number, format!("unknown property type '{}'", name)))
+}
+
+fn add_property(
+    elements: &mut [Element],
+    property: Property,
+    line_number: usize,
+) -> Result<(), (usize, String)> {
+    match elements.last_mut() {
+        Some(element) => {
+            element.properties.push(property);
+            Ok(())
+        }
+        None => Err((
+            line_number,
+            "property declared before any element".to_string(),
+        )),
+    }
+}
+
+enum Reader<'a> {
+    // The text not read yet.
+    Ascii(&'a str),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Reader<'_> {
+    fn remaining(&self) -> usize {
+        match self {
+            Reader::Ascii(text) => text.len(),
+            Reader::Binary { data, .. } => data.len(),
+        }
+    }
+
+    // The fewest bytes one item of `element` can take: a character per
+    // ascii value, or the scalars and list lengths in binary.
+    fn minimum_size(&self, element: &Element) -> usize {
+        match self {
+            Reader::Ascii(_) => element.properties.len(),
+            Reader::Binary { .. } => element
+                .properties
+                .iter()
+                .map(|property| match property.kind {
+                    PropertyKind::Scalar(kind) => kind.size(),
+                    PropertyKind::List { count, .. } => count.size(),
+                })
+                .sum(),
+        }
+    }
+
+    fn read(&mut self, kind: ScalarType) -> Result<f64, String> {
+        match self {
+            Reader::Ascii(text) => {
+                let rest = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+                let end = rest
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(rest.len());
+                if end == 0 {
+                    return Err("unexpected end of data".to_string());
+                }
+                let (word, rest) = rest.split_at(end);
+                *text = rest;
+                word.parse::<f64>()
+                    .map_err(|_| format!("invalid number '{}'", word))
+            }
+            Reader::Binary { data, big_endian } => {
+                let size = kind.size();
+                if data.len() < size {
+                    return Err("unexpected end of data".to_string());
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(&data[..size]);
+                *data = &data[size..];
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+                Ok(match kind {
+                    ScalarType::Int8 => f64::from(bytes[0] as i8),
+                    ScalarType::UInt8 => f64::from(bytes[0]),
+                    ScalarType::Int16 => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+                    ScalarType::UInt16 => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+                    ScalarType::Int32 => {
+                        f64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    }
+                    ScalarType::UInt32 => {
+                        f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    }
+                    ScalarType::Float32 => {
+                        f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    }
+                    ScalarType::Float64 => f64::from_le_bytes(bytes),
+                })
+            }
+        }
+    }
+
+    fn skip(&mut self, property: &Property) -> Result<(), String> {
+        match property.kind {
+            PropertyKind::Scalar(kind) => self.read(kind).map(|_| ()),
+            PropertyKind::List { count, item } => {
+                let count = self.list_length(count)?;
+                for _ in 0..count {
+                    self.read(item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn list_length(&mut self, kind: ScalarType) -> Result<usize, String> {
+        let count = self.read(kind)?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(format!("invalid list length {}", count));
+        }
+        Ok(count as usize)
+    }
+}
+
+// Where each vertex property goes: position, normal, uv and colour
+// components, in that order.
+const VERTEX_SLOTS: [&[&str]; 11] = [
+    &["x"],
+    &["y"],
+    &["z"],
+    &["nx"],
+    &["ny"],
+    &["nz"],
+    &["u", "s", "texture_u", "texture_s"],
+    &["v", "t", "texture_v", "texture_t"],
+    &["red", "r", "diffuse_red"],
+    &["green", "g", "diffuse_green"],
+    &["blue", "b", "diffuse_blue"],
+];
+
+fn read_vertices(
+    element: &Element,
+    reader: &mut Reader,
+    mesh: &mut PlyMesh,
+) -> Result<(), (usize, String)> {
+    let slots: Vec<Option<usize>> = element
+        .properties
+        .iter()
+        .map(|property| match property.kind {
+            PropertyKind::Scalar(_) => VERTEX_SLOTS
+                .iter()
+                .position(|names| names.contains(&property.name.as_str())),
+            PropertyKind::List { .. } => None,
+        })
+        .collect();
+    let has =
+        |first: usize, count: usize| (first..first + count).all(|slot| slots.contains(&Some(slot)));
+    if !has(0, 3) {
+        return Err((0, "vertices need x, y and z properties".to_string()));
+    }
+    let (has_normals, has_uvs, has_colours) = (has(3, 3), has(6, 2), has(8, 3));
+
+    mesh.positions.reserve(element.count);
+    for index in 0..element.count {
+        let mut values = [0.0f64; 11];
+        for (property, slot) in element.properties.iter().zip(slots.iter()) {
+            match (slot, &property.kind) {
+                (Some(slot), PropertyKind::Scalar(kind)) => {
+                    let value = reader.read(*kind).map_err(|message| (index, message))?;
+                    values[*slot] = if *slot >= 8 {
+                        value / kind.colour_scale()
+                    } else {
+                        value
+                    };
+                }
+                _ => reader.skip(property).map_err(|message| (index, message))?,
+            }
+        }
+
+        let v = values.map(|value| value as f32);
+        mesh.positions.push(Vec3::from(v[0], v[1], v[2]));
+        if has_normals {
+            mesh.normals.push(Vec3::from(v[3], v[4], v[5]));
+        }
+        if has_uvs {
+            mesh.uvs.push([v[6], v[7]]);
+        }
+        if has_colours {
+            // Colours are stored for display; squaring undoes the gamma of
+            // two the renderer applies on output.
+            let linear = |channel: f32| {
+                let channel = channel.clamp(0.0, 1.0);
+                channel * channel
+            };
+            mesh.colours
+                .push(Vec3::from(linear(v[8]), linear(v[9]), linear(v[10])));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    element: &Element,
+    reader: &mut Reader,
+    mesh: &mut PlyMesh,
+) -> Result<(), (usize, String)> {
+    let list = element.properties.iter().position(|property| {
+        matches!(property.kind, PropertyKind::List { .. })
+            && (property.name == "vertex_indices" || property.name == "vertex_index")
+    });
+    let list = match list {
+        Some(list) => list,
+        None => return Err((0, "faces need a vertex_indices list".to_string())),
+    };
+
+    mesh.indices.reserve(element.count);
+    let mut corners: Vec<u32> = Vec::new();
+    let mut polygon: Vec<Vec3> = Vec::new();
+    for index in 0..element.count {
+        for (position, property) in element.properties.iter().enumerate() {
+            let (count, item) = match property.kind {
+                PropertyKind::List { count, item } if position == list => (count, item),
+                _ => {
+                    reader.skip(property).map_err(|message| (index, message))?;
+                    continue;
+                }
+            };
+
+            let length = reader
+                .list_length(count)
+                .map_err(|message| (index, message))?;
+            corners.clear();
+            for _ in 0..length {
+                let vertex = reader.read(item).map_err(|message| (index, message))?;
+                if vertex < 0.0 || vertex > f64::from(u32::MAX) || vertex.fract() != 0.0 {
+                    return Err((index, format!("invalid vertex index {}", vertex)));
+                }
+                corners.push(vertex as u32);
+            }
+            if length < 3 {
+                return Err((
+                    index,
+                    format!("a face needs at least three vertices, found {}", length),
+                ));
+            }
+
+            if length == 3 {
+                mesh.indices.push([corners[0], corners[1], corners[2]]);
+                continue;
+            }
+            // Larger polygons need the vertex positions, which PLY files
+            // list before the faces.
+            polygon.clear();
+            for &corner in &corners {
+                match mesh.positions.get(corner as usize) {
+                    Some(point) => polygon.push(*point),
+                    None => {
+                        return Err((
+                            index,
+                            format!(
+                                "vertex {} is out of range, {} vertices have been read",
+                                corner,
+                                mesh.positions.len()
+                            ),
+                        ))
+                    }
+                }
+            }
+            for [a, b, c] in triangulate(&polygon) {
+                mesh.indices.push([corners[a], corners[b], corners[c]]);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn skip_element(element: &Element, reader: &mut Reader) -> Result<(), (usize, String)> {
+    for index in 0..element.count {
+        for property in &element.properties {
+            reader.skip(property).map_err(|message| (index, message))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, data: &[u8]) -> Result<PlyMesh, PlyError> {
+        let path =
+            std::env::temp_dir().join(format!("raytracer-ply-{}-{}.ply", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        let mesh = load_ply(&path);
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    fn parse_error(result: Result<PlyMesh, PlyError>) -> (String, String) {
+        match result {
+            Err(PlyError::Parse {
+                location, message, ..
+            }) => (location, message),
+            Err(err) => panic!("expected a parse error, got {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    fn xyz(v: &Vec3) -> [f32; 3] {
+        [*v.x(), *v.y(), *v.z()]
+    }
+
+    const ASCII_QUAD: &str = "ply\n\
+        format ascii 1.0\n\
+        comment a unit square\n\
+        element vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property float nx\nproperty float ny\nproperty float nz\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n\
+        0 0 0 0 0 1 255 0 0\n\
+        1 0 0 0 0 1 0 255 0\n\
+        1 1 0 0 0 1 0 0 255\n\
+        0 1 0 0 0 1 255 255 255\n\
+        4 0 1 2 3\n";
+
+    #[test]
+    fn parses_ascii() {
+        let mesh = load("ascii", ASCII_QUAD.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(xyz(&mesh.positions[2]), [1.0, 1.0, 0.0]);
+        assert_eq!(xyz(&mesh.normals[0]), [0.0, 0.0, 1.0]);
+        assert_eq!(xyz(&mesh.colours[1]), [0.0, 1.0, 0.0]);
+        assert!(mesh.uvs.is_empty());
+        // The quad is split into two triangles.
+        assert_eq!(mesh.indices.len(), 2);
+        assert!(mesh.indices.iter().flatten().all(|&index| index < 4));
+    }
+
+    // One triangle with a skipped property and element, in either byte
+    // order.
+    fn binary_triangle(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!(
+            "ply\nformat {} 1.0\n\
+             element vertex 3\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property short confidence\n\
+             element face 1\n\
+             property list uchar uint vertex_indices\n\
+             element edge 1\n\
+             property int vertex1\nproperty int vertex2\n\
+             end_header\n",
+            format
+        )
+        .into_bytes();
+        let float = |data: &mut Vec<u8>, value: f32| {
+            data.extend_from_slice(&if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        let int = |data: &mut Vec<u8>, value: u32| {
+            data.extend_from_slice(&if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        for position in [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 3.0, -1.5]] {
+            for value in position {
+                float(&mut data, value);
+            }
+            data.extend_from_slice(&[0, 7]);
+        }
+        data.push(3);
+        for index in [0, 1, 2] {
+            int(&mut data, index);
+        }
+        int(&mut data, 0);
+        int(&mut data, 1);
+        data
+    }
+
+    #[test]
+    fn parses_binary_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let mesh = load("binary", &binary_triangle(big_endian)).unwrap();
+            assert_eq!(xyz(&mesh.positions[2]), [0.0, 3.0, -1.5]);
+            assert_eq!(mesh.indices, [[0, 1, 2]]);
+            assert!(mesh.normals.is_empty() && mesh.colours.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_counts_beyond_the_data() {
+        // Far more elements than the file could hold must not be reserved.
+        let header = ASCII_QUAD.replace("element vertex 4", "element vertex 18446744073709551615");
+        let (location, message) = parse_error(load("huge-ascii", header.as_bytes()));
+        assert_eq!(location, "vertex");
+        assert!(message.contains("do not fit"), "{}", message);
+
+        let mut data = binary_triangle(false);
+        let count = data
+            .windows(16)
+            .position(|w| w == b"element vertex 3")
+            .unwrap()
+            + 15;
+        data[count] = b'9';
+        let (location, message) = parse_error(load("huge-binary", &data));
+        assert_eq!(location, "vertex");
+        assert!(message.contains("9 elements do not fit"), "{}", message);
+
+        let header = ASCII_QUAD.replace("element face 1", "element face 4000000000000");
+        let (location, message) = parse_error(load("huge-faces", header.as_bytes()));
+        assert_eq!(location, "face");
+        assert!(message.contains("do not fit"), "{}", message);
+    }
+
+    #[test]
+    fn reports_header_errors() {
+        let cases = [
+            ("plx\n", "header line 1", "not a PLY file"),
+            (
+                "ply\nelement vertex 1\nend_header\n",
+                "header line 3",
+                "does not declare a format",
+            ),
+            (
+                "ply\nformat ascii 2.0\n",
+                "header line 2",
+                "unsupported PLY version",
+            ),
+            (
+                "ply\nformat ascii 1.0\nproperty float x\n",
+                "header line 3",
+                "before any element",
+            ),
+            (
+                "ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\n",
+                "header line 4",
+                "unknown property type",
+            ),
+            (
+                "ply\nformat ascii 1.0\nelement vertex -1\n",
+                "header line 3",
+                "invalid element count",
+            ),
+            ("ply\nformat ascii 1.0\n", "header line 3", "no end_header"),
+        ];
+        for (data, expected_location, expected) in cases.iter() {
+            let (location, message) = parse_error(load("header", data.as_bytes()));
+            assert_eq!(location, *expected_location, "{}", data);
+            assert!(message.contains(expected), "{}: {}", data, message);
+        }
+    }
+
+    #[test]
+    fn reports_data_errors() {
+        let cases = [
+            (
+                ASCII_QUAD.replace("4 0 1 2 3", "2 0 1"),
+                "face 0",
+                "at least three vertices",
+            ),
+            (
+                ASCII_QUAD.replace("4 0 1 2 3", "3 0 1 7"),
+                "face data",
+                "uses vertex 7",
+            ),
+            (
+                ASCII_QUAD.replace("4 0 1 2 3", "4 0 1 2 9"),
+                "face 0",
+                "vertex 9 is out of range",
+            ),
+            (
+                ASCII_QUAD.replace("4 0 1 2 3", "3 0 1 -2"),
+                "face 0",
+                "invalid vertex index -2",
+            ),
+            (
+                ASCII_QUAD.replace("1 1 0 0 0 1", "1 one 0 0 0 1"),
+                "vertex 2",
+                "invalid number 'one'",
+            ),
+            (
+                ASCII_QUAD.replace("4 0 1 2 3\n", "4 0 1"),
+                "face 0",
+                "unexpected end of data",
+            ),
+            (
+                ASCII_QUAD.replace("property float z\n", "property float w\n"),
+                "vertex 0",
+                "need x, y and z",
+            ),
+            (
+                ASCII_QUAD.replace("vertex_indices", "corners"),
+                "face 0",
+                "vertex_indices list",
+            ),
+        ];
+        for (data, expected_location, expected) in cases.iter() {
+            let (location, message) = parse_error(load("data", data.as_bytes()));
+            assert_eq!(location, *expected_location, "{}", data);
+            assert!(message.contains(expected), "{}: {}", data, message);
+        }
+
+        let mut truncated = binary_triangle(true);
+        // Cut into the face, after the edge element.
+        truncated.truncate(truncated.len() - 8 - 6);
+        let (location, message) = parse_error(load("truncated", &truncated));
+        assert_eq!(location, "face 0");
+        assert!(message.contains("unexpected end of data"), "{}", message);
+    }
+}
@@ -0,0 +1,151 @@
+// Render progress as seen from outside the workers, and a text progress bar
+// for terminals.
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct Progress {
+    // The pass being rendered, counting from 1 and including passes restored
+    // from a checkpoint.
+    pub pass: u32,
+    pub pass_count: u32,
+    pub tiles_completed: usize,
+    pub tile_count: usize,
+    // Rows whose tiles have all finished in the current pass.
+    pub rows_completed: usize,
+    pub row_count: usize,
+    // Pixel samples in the image so far, including resumed ones.
+    pub samples_completed: u64,
+    pub samples_total: u64,
+    // Pixel samples and rays traced since this render started.
+    pub samples_rendered: u64,
+    pub rays_traced: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.samples_total == 0 {
+            1.0
+        } else {
+            (self.samples_completed as f64 / self.samples_total as f64) as f32
+        }
+    }
+
+    pub fn samples_per_second(&self) -> f64 {
+        per_second(self.samples_rendered, self.elapsed)
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        per_second(self.rays_traced, self.elapsed)
+    }
+
+    // Extrapolated from the sample rate so far; None until there is one.
+    pub fn eta(&self) -> Option<Duration> {
+        let rate = self.samples_per_second();
+        if rate <= 0.0 {
+            return None;
+        }
+        let remaining = self.samples_total.saturating_sub(self.samples_completed);
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        count as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+// Redraws a single status line on stderr using carriage returns.
+pub struct ProgressBar {
+    width: usize,
+    last_length: usize,
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        ProgressBar {
+            width: 30,
+            last_length: 0,
+        }
+    }
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, progress: &Progress) {
+        let filled = ((progress.fraction() * self.width as f32) as usize).min(self.width);
+        let eta = match progress.eta() {
+            Some(eta) => format_duration(eta),
+            None => "--".to_string(),
+        };
+        let line = format!(
+            "[{}{}] {:5.1}%  pass {}/{}  tiles {}/{}  rows {}/{}  {} samples/s  {} rays/s  elapsed {}  ETA {}",
+            "#".repeat(filled),
+            " ".repeat(self.width - filled),
+            progress.fraction() * 100.0,
+            progress.pass,
+            progress.pass_count,
+            progress.tiles_completed,
+            progress.tile_count,
+            progress.rows_completed,
+            progress.row_count,
+            format_rate(progress.samples_per_second()),
+            format_rate(progress.rays_per_second()),
+            format_duration(progress.elapsed),
+            eta
+        );
+
+        // Pad with spaces to wipe out the tail of a longer previous line.
+        let padding = self.last_length.saturating_sub(line.len());
+        self.last_length = line.len();
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+        let _ = write!(out, "\r{}{}", line, " ".repeat(padding));
+        let _ = out.flush();
+    }
+
+    // Moves past the status line so later output starts on a fresh line.
+    pub fn finish(&mut self) {
+        if self.last_length > 0 {
+            eprintln!();
+            self.last_length = 0;
+        }
+    }
+}
+
+fn format_rate(rate: f64) -> String {
+    if rate >= 1.0e9 {
+        format!("{:.2}G", rate / 1.0e9)
+    } else if rate >= 1.0e6 {
+        format!("{:.2}M", rate / 1.0e6)
+    } else if rate >= 1.0e3 {
+        format!("{:.2}k", rate / 1.0e3)
+    } else {
+        format!("{:.0}", rate)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}h{:02}m{:02}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
@@ -0,0 +1,383 @@
+// Four-wide BVH nodes, collapsed from the binary tree. Each node keeps the
+// boxes of its up to four children side by side in SSE registers, one per
+// bound and axis, so a ray is tested against all four at once. Children
+// the ray hits are visited nearest first.
+//
+// A packet of rays walks the tree the other way round: each child's box is
+// tested against the four rays at once, and a child is visited by the rays
+// that hit it, ordered by the nearest of them.
+
+use crate::aabb::Aabb;
+use crate::bvh::{Closest, LinearNode, MAX_DEPTH};
+use crate::hitable::Hitable;
+use crate::packet::{self, RayPacket, PACKET_SIZE};
+use crate::ray::*;
+use crate::vec3::Vec3;
+
+use std::arch::x86_64::*;
+use std::mem::MaybeUninit;
+
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+pub(crate) struct WideNode {
+    // Lane i holds child i; lanes past `child_count` are ignored.
+    min: [__m128; 3],
+    max: [__m128; 3],
+    // As for `LinearNode`: a leaf's first primitive and primitive count,
+    // or for an interior node the index of a wide node and a count of 0.
+    children: [u32; 4],
+    counts: [u16; 4],
+    child_count: u8,
+}
+
+impl WideNode {
+    fn child_corners(&self, slot: usize) -> ([f32; 3], [f32; 3]) {
+        let lane = |lanes: &__m128| unsafe { *(lanes as *const __m128 as *const f32).add(slot) };
+        (self.min.each_ref().map(lane), self.max.each_ref().map(lane))
+    }
+
+    fn child_bounds(&self, slot: usize) -> Aabb {
+        let (min, max) = self.child_corners(slot);
+        Aabb::build(
+            Vec3::from(min[0], min[1], min[2]),
+            Vec3::from(max[0], max[1], max[2]),
+        )
+    }
+
+    // The box around all children.
+    pub(crate) fn bounds(&self) -> Aabb {
+        (1..self.child_count as usize).fold(self.child_bounds(0), |bounds, slot| {
+            Aabb::surrounding_box(&bounds, &self.child_bounds(slot))
+        })
+    }
+
+    // A mask of the children whose boxes the ray passes through within the
+    // range, and where it enters each. The same slab test as `Aabb::hit`,
+    // down to how NaNs fall out of the min and max.
+    unsafe fn hit_children(
+        &self,
+        origin: &[__m128; 3],
+        inv_d: &[__m128; 3],
+        t_min: f32,
+        t_max: f32,
+    ) -> (i32, __m128) {
+        let mut near = [_mm_setzero_ps(); 3];
+        let mut far = [_mm_setzero_ps(); 3];
+        for axis in 0..3 {
+            let t0 = _mm_mul_ps(_mm_sub_ps(self.min[axis], origin[axis]), inv_d[axis]);
+            let t1 = _mm_mul_ps(_mm_sub_ps(self.max[axis], origin[axis]), inv_d[axis]);
+            near[axis] = _mm_min_ps(t0, t1);
+            far[axis] = _mm_max_ps(t0, t1);
+        }
+        let near = _mm_max_ps(
+            _mm_set1_ps(t_min),
+            _mm_max_ps(near[0], _mm_max_ps(near[1], near[2])),
+        );
+        let far = _mm_min_ps(
+            _mm_set1_ps(t_max),
+            _mm_min_ps(far[0], _mm_min_ps(far[1], far[2])),
+        );
+        let live = (1 << self.child_count) - 1;
+        (_mm_movemask_ps(_mm_cmpge_ps(far, near)) & live, near)
+    }
+}
+
+// Builds the wide nodes for a binary tree, root first.
+pub(crate) fn collapse(binary: &[LinearNode]) -> Vec<WideNode> {
+    let mut nodes = Vec::with_capacity(binary.len() / 2 + 1);
+    if !binary.is_empty() {
+        collapse_node(binary, 0, &mut nodes);
+    }
+    nodes
+}
+
+// Appends the wide node standing in for binary node `index` and its
+// subtree, returning where it went. Its children are found by repeatedly
+// opening up the interior child with the largest box, the one rays are most
+// likely to enter, until there are four.
+fn collapse_node(binary: &[LinearNode], index: usize, nodes: &mut Vec<WideNode>) -> u32 {
+    let mut children = if binary[index].count > 0 {
+        vec![index]
+    } else {
+        vec![index + 1, binary[index].offset as usize]
+    };
+    while children.len() < 4 {
+        let largest = children
+            .iter()
+            .enumerate()
+            .filter(|(_, &child)| binary[child].count == 0)
+            .max_by(|(_, &a), (_, &b)| {
+                let area = |child: usize| binary[child].bounds().surface_area();
+                area(a).total_cmp(&area(b))
+            })
+            .map(|(slot, _)| slot);
+        match largest {
+            Some(slot) => {
+                let child = children[slot];
+                children[slot] = child + 1;
+                children.insert(slot + 1, binary[child].offset as usize);
+            }
+            None => break,
+        }
+    }
+
+    let wide_index = nodes.len();
+    nodes.push(unsafe { std::mem::zeroed() });
+
+    let mut lanes = [[f32::INFINITY; 4]; 6];
+    let mut node_children = [0u32; 4];
+    let mut counts = [0u16; 4];
+    for (slot, &child) in children.iter().enumerate() {
+        let node = &binary[child];
+        for axis in 0..3 {
+            lanes[axis][slot] = node.min[axis];
+            lanes[axis + 3][slot] = node.max[axis];
+        }
+        if node.count > 0 {
+            node_children[slot] = node.offset;
+            counts[slot] = node.count;
+        } else {
+            node_children[slot] = collapse_node(binary, child, nodes);
+        }
+    }
+
+    let load = |lane: &[f32; 4]| unsafe { _mm_loadu_ps(lane.as_ptr()) };
+    nodes[wide_index] = WideNode {
+        min: [load(&lanes[0]), load(&lanes[1]), load(&lanes[2])],
+        max: [load(&lanes[3]), load(&lanes[4]), load(&lanes[5])],
+        children: node_children,
+        counts,
+        child_count: children.len() as u8,
+    };
+    wide_index as u32
+}
+
+#[derive(Copy, Clone, Default)]
+struct StackEntry {
+    child: u32,
+    count: u16,
+    // Where the ray enters the child's box.
+    t_near: f32,
+}
+
+// Each visit replaces one entry with up to four.
+const STACK_SIZE: usize = MAX_DEPTH * 3 + 1;
+
+pub(crate) fn hit<'a>(
+    nodes: &[WideNode],
+    primitives: &'a [Box<dyn Hitable>],
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    if nodes.is_empty() {
+        return None;
+    }
+    let mut closest = Closest::new(t_max);
+    let root = StackEntry {
+        child: 0,
+        count: 0,
+        t_near: t_min,
+    };
+    walk(nodes, primitives, ray, t_min, root, &mut closest);
+    closest.record
+}
+
+// Visits the subtree of `start` for a single ray.
+fn walk<'a>(
+    nodes: &[WideNode],
+    primitives: &'a [Box<dyn Hitable>],
+    ray: &Ray,
+    t_min: f32,
+    start: StackEntry,
+    closest: &mut Closest<'a>,
+) {
+    let inv_d = ray.direction.invert_elems();
+    let (origin, inv_d) = unsafe {
+        (
+            [
+                _mm_set1_ps(*ray.origin.x()),
+                _mm_set1_ps(*ray.origin.y()),
+                _mm_set1_ps(*ray.origin.z()),
+            ],
+            [
+                _mm_set1_ps(*inv_d.x()),
+                _mm_set1_ps(*inv_d.y()),
+                _mm_set1_ps(*inv_d.z()),
+            ],
+        )
+    };
+
+    // Left uninitialised, as clearing it would cost more than the
+    // traversal of a short ray. Only entries below `stack_size` are read.
+    let mut stack = [MaybeUninit::<StackEntry>::uninit(); STACK_SIZE];
+    stack[0] = MaybeUninit::new(start);
+    let mut stack_size = 1;
+    while stack_size > 0 {
+        stack_size -= 1;
+        let entry = unsafe { stack[stack_size].assume_init() };
+        // A closer hit may have turned up since the box was tested.
+        if entry.t_near > closest.t_max {
+            continue;
+        }
+
+        if entry.count > 0 {
+            let first = entry.child as usize;
+            let leaf = &primitives[first..first + entry.count as usize];
+            closest.hit_leaf(leaf, first, ray, t_min);
+            continue;
+        }
+
+        let node = &nodes[entry.child as usize];
+        let (mask, near) = unsafe { node.hit_children(&origin, &inv_d, t_min, closest.t_max) };
+        let mut near_lanes = [0.0f32; 4];
+        unsafe { _mm_storeu_ps(near_lanes.as_mut_ptr(), near) };
+        let mut mask = mask;
+
+        // Sorted farthest first, so the nearest ends up on top of the stack.
+        let mut hits = [StackEntry::default(); 4];
+        let mut hit_count = 0;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            let hit = StackEntry {
+                child: node.children[slot],
+                count: node.counts[slot],
+                t_near: near_lanes[slot],
+            };
+            let mut position = hit_count;
+            while position > 0 && hits[position - 1].t_near < hit.t_near {
+                hits[position] = hits[position - 1];
+                position -= 1;
+            }
+            hits[position] = hit;
+            hit_count += 1;
+        }
+        for hit in &hits[..hit_count] {
+            stack[stack_size] = MaybeUninit::new(*hit);
+            stack_size += 1;
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct PacketEntry {
+    child: u32,
+    count: u16,
+    // The rays that hit the child's box, and where each enters it.
+    mask: i32,
+    t_near: __m128,
+}
+
+pub(crate) fn hit_packet<'a>(
+    nodes: &[WideNode],
+    primitives: &'a [Box<dyn Hitable>],
+    packet: &RayPacket,
+    active: i32,
+    t_min: f32,
+    t_max: &[f32; PACKET_SIZE],
+) -> [Option<HitRecord<'a>>; PACKET_SIZE] {
+    if nodes.is_empty() || active == 0 {
+        return packet::no_hits();
+    }
+    let mut closest = t_max.map(Closest::new);
+    let closest_t = |closest: &[Closest<'_>; PACKET_SIZE]| unsafe {
+        _mm_setr_ps(
+            closest[0].t_max,
+            closest[1].t_max,
+            closest[2].t_max,
+            closest[3].t_max,
+        )
+    };
+
+    let mut stack = [MaybeUninit::<PacketEntry>::uninit(); STACK_SIZE];
+    stack[0] = MaybeUninit::new(PacketEntry {
+        child: 0,
+        count: 0,
+        mask: active,
+        t_near: unsafe { _mm_set1_ps(t_min) },
+    });
+    let mut stack_size = 1;
+    while stack_size > 0 {
+        stack_size -= 1;
+        let entry = unsafe { stack[stack_size].assume_init() };
+        // Rays that have since found a closer hit drop out.
+        let t_far = closest_t(&closest);
+        let mask = entry.mask & unsafe { _mm_movemask_ps(_mm_cmple_ps(entry.t_near, t_far)) };
+        if mask == 0 {
+            continue;
+        }
+        // A ray left on its own is quicker to trace alone.
+        if mask & (mask - 1) == 0 {
+            let lane = mask.trailing_zeros() as usize;
+            let mut near_lanes = [0.0f32; PACKET_SIZE];
+            unsafe { _mm_storeu_ps(near_lanes.as_mut_ptr(), entry.t_near) };
+            let start = StackEntry {
+                child: entry.child,
+                count: entry.count,
+                t_near: near_lanes[lane],
+            };
+            let ray = &packet.rays[lane];
+            walk(nodes, primitives, ray, t_min, start, &mut closest[lane]);
+            continue;
+        }
+
+        if entry.count > 0 {
+            let first = entry.child as usize;
+            let leaf = &primitives[first..first + entry.count as usize];
+            for (offset, primitive) in leaf.iter().enumerate() {
+                let limits = closest.each_ref().map(|closest| closest.limit);
+                let records = primitive.hit_packet(packet, mask, t_min, &limits);
+                for (lane, record) in (0..PACKET_SIZE).zip(records) {
+                    if let Some(record) = record {
+                        closest[lane].offer(record, first + offset);
+                    }
+                }
+            }
+            continue;
+        }
+
+        // Sorted farthest first by the nearest ray, as for a single ray.
+        let node = &nodes[entry.child as usize];
+        let mut hits = [(f32::INFINITY, entry); 4];
+        let mut hit_count = 0;
+        let active = packet::lane_mask(mask);
+        for slot in 0..node.child_count as usize {
+            let (min, max) = node.child_corners(slot);
+            let (lanes, near) = packet.hit_box(min, max, t_min, t_far);
+            let (lanes, nearest) = unsafe {
+                let lanes = _mm_and_ps(lanes, active);
+                // The smallest entry point of the lanes that hit.
+                let near_hit = _mm_or_ps(
+                    _mm_and_ps(lanes, near),
+                    _mm_andnot_ps(lanes, _mm_set1_ps(f32::INFINITY)),
+                );
+                let nearest =
+                    _mm_min_ps(near_hit, _mm_shuffle_ps(near_hit, near_hit, 0b10_11_00_01));
+                let nearest = _mm_min_ps(nearest, _mm_shuffle_ps(nearest, nearest, 0b01_00_11_10));
+                (_mm_movemask_ps(lanes), _mm_cvtss_f32(nearest))
+            };
+            if lanes == 0 {
+                continue;
+            }
+            let hit = PacketEntry {
+                child: node.children[slot],
+                count: node.counts[slot],
+                mask: lanes,
+                t_near: near,
+            };
+            let mut position = hit_count;
+            while position > 0 && hits[position - 1].0 < nearest {
+                hits[position] = hits[position - 1];
+                position -= 1;
+            }
+            hits[position] = (nearest, hit);
+            hit_count += 1;
+        }
+        for (_, hit) in &hits[..hit_count] {
+            stack[stack_size] = MaybeUninit::new(*hit);
+            stack_size += 1;
+        }
+    }
+    closest.map(|closest| closest.record)
+}
@@ -0,0 +1,494 @@
+// Analytic quadric primitives: disks and annuli, cylinders, cones and
+// paraboloids, each placed along an arbitrary axis.
+//
+// Every shape is intersected in a local frame where the axis is z and the
+// base sits at the origin, and bounded by a box that is exact for its
+// orientation rather than the box around a transformed local box.
+
+use crate::aabb::{Aabb, MIN_BOX_EXTENT};
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::ray::*;
+use crate::vec3::*;
+
+use std::f64::consts::PI;
+
+// An orthonormal basis with `w` along the shape's axis.
+struct Frame {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    // Normalised with a full square root: `make_normalised` is an
+    // approximation that would skew the shapes against their boxes.
+    fn new(origin: Vec3, axis: &Vec3) -> Self {
+        let w = axis / axis.length();
+        let helper = if w.x().abs() > 0.9 {
+            Vec3::from(0.0, 1.0, 0.0)
+        } else {
+            Vec3::from(1.0, 0.0, 0.0)
+        };
+        let u = cross(&helper, &w);
+        let u = &u / u.length();
+        let v = cross(&w, &u);
+        Frame { origin, u, v, w }
+    }
+
+    // The ray's origin and direction in local coordinates, in double
+    // precision so that large CAD coordinates keep their accuracy.
+    fn local_ray(&self, ray: &Ray) -> ([f64; 3], [f64; 3]) {
+        let wide = |vector: &Vec3| {
+            [
+                f64::from(*vector.x()),
+                f64::from(*vector.y()),
+                f64::from(*vector.z()),
+            ]
+        };
+        let (u, v, w) = (wide(&self.u), wide(&self.v), wide(&self.w));
+        let project = |vector: [f64; 3]| {
+            let along =
+                |axis: &[f64; 3]| vector[0] * axis[0] + vector[1] * axis[1] + vector[2] * axis[2];
+            [along(&u), along(&v), along(&w)]
+        };
+        let (origin, base) = (wide(&ray.origin), wide(&self.origin));
+        (
+            project([
+                origin[0] - base[0],
+                origin[1] - base[1],
+                origin[2] - base[2],
+            ]),
+            project(wide(&ray.direction)),
+        )
+    }
+
+    fn world_vector(&self, local: [f64; 3]) -> Vec3 {
+        &self.u * local[0] as f32 + &self.v * local[1] as f32 + &self.w * local[2] as f32
+    }
+
+    // Half the extent, along each world axis, of a circle of `radius`
+    // perpendicular to the axis.
+    fn circle_extent(&self, radius: f32) -> Vec3 {
+        let extent = |axis: f32| radius * (1.0 - axis * axis).max(0.0).sqrt();
+        Vec3::from(
+            extent(*self.w.x()),
+            extent(*self.w.y()),
+            extent(*self.w.z()),
+        )
+    }
+
+    fn circle_box(&self, height: f32, radius: f32) -> Aabb {
+        let center = self.origin + &self.w * height;
+        let extent = self.circle_extent(radius);
+        Aabb::build(center - extent, center + extent)
+    }
+}
+
+// A candidate intersection in local coordinates.
+struct LocalHit {
+    t: f64,
+    normal: [f64; 3],
+    u: f64,
+    v: f64,
+}
+
+impl LocalHit {
+    // Closed shapes keep outward normals, which dielectrics rely on to tell
+    // entering from leaving. Open ones are thin sheets whose inside is just
+    // as visible as their outside, so the normal faces the ray instead.
+    fn facing(mut self, direction: &[f64; 3], closed: bool) -> LocalHit {
+        let n = &mut self.normal;
+        if !closed && n[0] * direction[0] + n[1] * direction[1] + n[2] * direction[2] > 0.0 {
+            *n = [-n[0], -n[1], -n[2]];
+        }
+        self
+    }
+
+    fn closer(self, other: Option<LocalHit>) -> LocalHit {
+        match other {
+            Some(other) if other.t < self.t => other,
+            _ => self,
+        }
+    }
+
+    fn into_record<'a>(
+        self,
+        frame: &Frame,
+        ray: &Ray,
+        material: &'a dyn Material,
+    ) -> HitRecord<'a> {
+        let t = self.t as f32;
+        HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: frame.world_vector(self.normal).make_normalised(),
+            u: self.u as f32,
+            v: self.v as f32,
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material,
+        }
+    }
+}
+
+// Roots of a t^2 + b t + c in increasing order, using the form that avoids
+// cancellation. A zero `a` leaves the single root of the linear equation.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+// Angle around the axis, mapped to [0, 1).
+fn azimuth(x: f64, y: f64) -> f64 {
+    let phi = y.atan2(x);
+    if phi < 0.0 {
+        (phi + 2.0 * PI) / (2.0 * PI)
+    } else {
+        phi / (2.0 * PI)
+    }
+}
+
+// The first root in (t_min, t_max) whose hit point lies between heights 0
+// and `height`, with that point.
+fn surface_root(
+    roots: (f64, f64),
+    origin: &[f64; 3],
+    direction: &[f64; 3],
+    height: f64,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, [f64; 3])> {
+    [roots.0, roots.1].iter().find_map(|&t| {
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let point = [
+            origin[0] + t * direction[0],
+            origin[1] + t * direction[1],
+            origin[2] + t * direction[2],
+        ];
+        if (0.0..=height).contains(&point[2]) {
+            Some((t, point))
+        } else {
+            None
+        }
+    })
+}
+
+// The flat ring between `inner` and `outer` at `height`, facing along the
+// axis or against it. v runs from the inner edge to the outer one.
+#[allow(clippy::too_many_arguments)]
+fn hit_ring(
+    origin: &[f64; 3],
+    direction: &[f64; 3],
+    height: f64,
+    inner: f64,
+    outer: f64,
+    facing: f64,
+    t_min: f64,
+    t_max: f64,
+) -> Option<LocalHit> {
+    let t = (height - origin[2]) / direction[2];
+    // Also rejects the NaN of a ray running inside the plane.
+    if !(t > t_min && t < t_max) {
+        return None;
+    }
+    let x = origin[0] + t * direction[0];
+    let y = origin[1] + t * direction[1];
+    let distance = (x * x + y * y).sqrt();
+    if distance < inner || distance > outer {
+        return None;
+    }
+    Some(LocalHit {
+        t,
+        normal: [0.0, 0.0, facing],
+        u: azimuth(x, y),
+        v: (distance - inner) / (outer - inner),
+    })
+}
+
+// A flat disk facing along `normal`, or an annulus when it has a hole.
+pub struct Disk {
+    frame: Frame,
+    inner_radius: f32,
+    radius: f32,
+    material: Box<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Box<dyn Material>) -> Self {
+        Self::annulus(center, normal, 0.0, radius, material)
+    }
+
+    // The ring between `inner_radius` and `radius`.
+    pub fn annulus(
+        center: Vec3,
+        normal: Vec3,
+        inner_radius: f32,
+        radius: f32,
+        material: Box<dyn Material>,
+    ) -> Self {
+        Disk {
+            frame: Frame::new(center, &normal),
+            inner_radius,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (origin, direction) = self.frame.local_ray(ray);
+        let hit = hit_ring(
+            &origin,
+            &direction,
+            0.0,
+            f64::from(self.inner_radius),
+            f64::from(self.radius),
+            1.0,
+            f64::from(t_min),
+            f64::from(t_max),
+        )?;
+        Some(hit.into_record(&self.frame, ray, &*self.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.frame
+            .circle_box(0.0, self.radius)
+            .padded(MIN_BOX_EXTENT)
+    }
+}
+
+// A cylinder from the center of its base to the center of its top. Without
+// caps it is an open tube, shaded on both sides.
+pub struct Cylinder {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    capped: bool,
+    material: Box<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f32, material: Box<dyn Material>) -> Self {
+        let axis = top - base;
+        Cylinder {
+            frame: Frame::new(base, &axis),
+            height: axis.length(),
+            radius,
+            capped: false,
+            material,
+        }
+    }
+
+    // Closes both ends with disks.
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+
+impl Hitable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(ray);
+        let (t_min, t_max) = (f64::from(t_min), f64::from(t_max));
+        let (radius, height) = (f64::from(self.radius), f64::from(self.height));
+
+        let side = solve_quadratic(
+            d[0] * d[0] + d[1] * d[1],
+            2.0 * (o[0] * d[0] + o[1] * d[1]),
+            o[0] * o[0] + o[1] * o[1] - radius * radius,
+        )
+        .and_then(|roots| surface_root(roots, &o, &d, height, t_min, t_max))
+        .map(|(t, p)| LocalHit {
+            t,
+            normal: [p[0], p[1], 0.0],
+            u: azimuth(p[0], p[1]),
+            v: p[2] / height,
+        });
+
+        let mut closest = side.map(|hit| hit.facing(&d, self.capped));
+        if self.capped {
+            for &(cap_height, facing) in &[(0.0, -1.0), (height, 1.0)] {
+                if let Some(cap) = hit_ring(&o, &d, cap_height, 0.0, radius, facing, t_min, t_max) {
+                    closest = Some(cap.closer(closest));
+                }
+            }
+        }
+        Some(closest?.into_record(&self.frame, ray, &*self.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::surrounding_box(
+            &self.frame.circle_box(0.0, self.radius),
+            &self.frame.circle_box(self.height, self.radius),
+        )
+        .padded(MIN_BOX_EXTENT)
+    }
+}
+
+// A cone with a circular base of `radius` narrowing to `apex`. The cap
+// closes the base.
+pub struct Cone {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    capped: bool,
+    material: Box<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f32, material: Box<dyn Material>) -> Self {
+        let axis = apex - base;
+        Cone {
+            frame: Frame::new(base, &axis),
+            height: axis.length(),
+            radius,
+            capped: false,
+            material,
+        }
+    }
+
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+
+impl Hitable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(ray);
+        let (t_min, t_max) = (f64::from(t_min), f64::from(t_max));
+        let (radius, height) = (f64::from(self.radius), f64::from(self.height));
+
+        // x^2 + y^2 = k (height - z)^2, the squared radius shrinking
+        // linearly to the apex.
+        let k = (radius / height) * (radius / height);
+        let to_apex = height - o[2];
+        let side = solve_quadratic(
+            d[0] * d[0] + d[1] * d[1] - k * d[2] * d[2],
+            2.0 * (o[0] * d[0] + o[1] * d[1] + k * to_apex * d[2]),
+            o[0] * o[0] + o[1] * o[1] - k * to_apex * to_apex,
+        )
+        .and_then(|roots| surface_root(roots, &o, &d, height, t_min, t_max))
+        .map(|(t, p)| LocalHit {
+            t,
+            normal: [p[0], p[1], k * (height - p[2])],
+            u: azimuth(p[0], p[1]),
+            v: p[2] / height,
+        });
+
+        let mut closest = side.map(|hit| hit.facing(&d, self.capped));
+        if self.capped {
+            if let Some(cap) = hit_ring(&o, &d, 0.0, 0.0, radius, -1.0, t_min, t_max) {
+                closest = Some(cap.closer(closest));
+            }
+        }
+        Some(closest?.into_record(&self.frame, ray, &*self.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let apex = self.frame.origin + &self.frame.w * self.height;
+        let base = self.frame.circle_box(0.0, self.radius);
+        Aabb::build(base.min.min(&apex), base.max.max(&apex)).padded(MIN_BOX_EXTENT)
+    }
+}
+
+// A paraboloid of revolution with its vertex at `vertex`, opening towards
+// `top` where it reaches `radius`. The cap closes the open end.
+pub struct Paraboloid {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    capped: bool,
+    material: Box<dyn Material>,
+}
+
+impl Paraboloid {
+    pub fn new(vertex: Vec3, top: Vec3, radius: f32, material: Box<dyn Material>) -> Self {
+        let axis = top - vertex;
+        Paraboloid {
+            frame: Frame::new(vertex, &axis),
+            height: axis.length(),
+            radius,
+            capped: false,
+            material,
+        }
+    }
+
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+
+    // How far the surface reaches along a world axis whose component along
+    // the shape's axis is `along`: the largest along * z + r(z) * across,
+    // with r(z) = radius * sqrt(z / height), over 0 <= z <= height.
+    fn reach(&self, along: f32) -> f32 {
+        let across = (1.0 - along * along).max(0.0).sqrt();
+        let z = if along < 0.0 {
+            (self.radius * self.radius * across * across / (4.0 * along * along * self.height))
+                .min(self.height)
+        } else {
+            self.height
+        };
+        along * z + self.radius * (z / self.height).sqrt() * across
+    }
+}
+
+impl Hitable for Paraboloid {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(ray);
+        let (t_min, t_max) = (f64::from(t_min), f64::from(t_max));
+        let (radius, height) = (f64::from(self.radius), f64::from(self.height));
+
+        // x^2 + y^2 = k z, widening to `radius` at `height`.
+        let k = radius * radius / height;
+        let side = solve_quadratic(
+            d[0] * d[0] + d[1] * d[1],
+            2.0 * (o[0] * d[0] + o[1] * d[1]) - k * d[2],
+            o[0] * o[0] + o[1] * o[1] - k * o[2],
+        )
+        .and_then(|roots| surface_root(roots, &o, &d, height, t_min, t_max))
+        .map(|(t, p)| LocalHit {
+            t,
+            normal: [2.0 * p[0], 2.0 * p[1], -k],
+            u: azimuth(p[0], p[1]),
+            v: p[2] / height,
+        });
+
+        let mut closest = side.map(|hit| hit.facing(&d, self.capped));
+        if self.capped {
+            if let Some(cap) = hit_ring(&o, &d, height, 0.0, radius, 1.0, t_min, t_max) {
+                closest = Some(cap.closer(closest));
+            }
+        }
+        Some(closest?.into_record(&self.frame, ray, &*self.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let vertex = self.frame.origin;
+        let w = &self.frame.w;
+        let reach = |axis: usize| (self.reach(*w.get(axis)), self.reach(-*w.get(axis)));
+        let (x, y, z) = (reach(0), reach(1), reach(2));
+        Aabb::build(
+            vertex - Vec3::from(x.1, y.1, z.1),
+            vertex + Vec3::from(x.0, y.0, z.0),
+        )
+        .padded(MIN_BOX_EXTENT)
+    }
+}
@@ -1,19 +1,20 @@
 use crate::material::Material;
 use crate::vec3::Vec3;
 
+#[derive(Default)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // When within the camera's shutter interval the ray was sent, for
+    // objects that move.
+    pub time: f32,
+    // A number in (0, 1] from the sample's random stream. Mixed with where
+    // the ray enters a participating medium, it sets how far the ray gets
+    // into it before scattering.
+    pub medium_sample: f32,
 }
 
 impl Ray {
-    pub fn default() -> Ray {
-        Ray {
-            origin: Vec3::default(),
-            direction: Vec3::default(),
-        }
-    }
-
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.origin + (&self.direction * t)
     }
@@ -23,5 +24,10 @@ pub struct HitRecord<'a> {
     pub t: f32,
     pub p: Vec3,
     pub normal: Vec3,
+    // Surface texture coordinates at the hit point.
+    pub u: f32,
+    pub v: f32,
+    // Surface colour from vertex colours, white where there are none.
+    pub colour: Vec3,
     pub material: &'a dyn Material,
 }
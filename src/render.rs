@@ -0,0 +1,595 @@
+use crate::camera::Camera;
+use crate::checkpoint::Checkpoint;
+use crate::image::{Image, OutputOptions};
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::progress::Progress;
+use crate::ray::{HitRecord, Ray};
+use crate::rng::{self, Random};
+use crate::tiles::{self, TileOrder};
+use crate::vec3::Vec3;
+use crate::world::World;
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often `render_progressive` reports progress while a pass is running.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// The range along each ray in which hits count.
+const MIN_THING: f32 = 0.001;
+const MAX_THING: f32 = 1.0e10;
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u32,
+    // Samples per pixel added by each progressive pass.
+    pub pass_samples: u32,
+    pub max_depth: i32,
+    pub thread_count: usize,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub seed: u64,
+}
+
+// Linear radiance, top row first.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 3840,
+            height: 2160,
+            samples_per_pixel: 1024,
+            pass_samples: 16,
+            max_depth: 20,
+            thread_count: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            seed: 42,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+}
+
+impl Framebuffer {
+    pub fn image(&self) -> Image<'_> {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: &self.pixels,
+        }
+    }
+
+    pub fn save(&self, path: &Path, options: &OutputOptions) -> io::Result<()> {
+        self.image().save(path, options)
+    }
+}
+
+// Renders the whole image in one go.
+pub fn render(camera: Camera, world: World, settings: &RenderSettings) -> Framebuffer {
+    let mut state = Checkpoint::new(settings.width, settings.height, settings.seed, 0);
+    render_progressive(
+        camera,
+        world,
+        settings,
+        &mut state,
+        &mut |_| {},
+        &mut |_| {},
+    );
+    Framebuffer {
+        width: settings.width,
+        height: settings.height,
+        pixels: state.average(),
+    }
+}
+
+// Renders the remaining passes into `state`, calling `on_pass` after each
+// one and `on_progress` a few times a second from the calling thread. Every
+// sample draws from its own random stream, derived from the seed, the pixel
+// and the sample index, and is added to its pixel in sample order.
+// The image is therefore bit-identical whatever the thread count, tile
+// layout or pass size, and a resumed render matches an uninterrupted one.
+//
+// Panics if the settings ask for no threads, empty tiles or empty passes,
+// or if `state` was made for a different image size.
+pub fn render_progressive(
+    camera: Camera,
+    world: World,
+    settings: &RenderSettings,
+    state: &mut Checkpoint,
+    on_pass: &mut dyn FnMut(&Checkpoint),
+    on_progress: &mut dyn FnMut(&Progress),
+) {
+    // Without a worker no pixel would be rendered, yet the samples would be
+    // counted as done.
+    assert!(
+        settings.thread_count > 0,
+        "RenderSettings::thread_count must be at least 1"
+    );
+    assert!(
+        settings.tile_size > 0,
+        "RenderSettings::tile_size must be at least 1"
+    );
+    assert!(
+        settings.pass_samples > 0,
+        "RenderSettings::pass_samples must be at least 1"
+    );
+    assert!(
+        state.width == settings.width && state.height == settings.height,
+        "the render state is {}x{} but the settings are for {}x{}",
+        state.width,
+        state.height,
+        settings.width,
+        settings.height
+    );
+    let nx = settings.width;
+    let ny = settings.height;
+    let nxd = nx as f32;
+    let nyd = ny as f32;
+    let max_depth = settings.max_depth;
+    let tile_size = settings.tile_size;
+    let seed = state.seed;
+
+    let arc_world = Arc::new(world);
+    let arc_tiles = Arc::new(tiles::build_tiles(
+        nx,
+        ny,
+        settings.tile_size,
+        settings.tile_order,
+    ));
+    // Every pixel has received the same number of samples so far; passes
+    // carry on from there even if the pass size has changed since.
+    let mut first_sample = state.sample_counts.first().copied().unwrap_or(0);
+
+    let pixel_count = (nx * ny) as u64;
+    let tile_count = arc_tiles.len();
+    let band_count = ny.div_ceil(settings.tile_size);
+    let pass_count = state.passes_completed
+        + settings
+            .samples_per_pixel
+            .saturating_sub(first_sample)
+            .div_ceil(settings.pass_samples);
+    let start_time = Instant::now();
+    let mut samples_rendered = 0u64;
+    let mut rays_traced = 0u64;
+
+    while first_sample < settings.samples_per_pixel {
+        let pass_samples = settings
+            .pass_samples
+            .min(settings.samples_per_pixel - first_sample);
+
+        let mut workers: Vec<std::thread::JoinHandle<()>> =
+            Vec::with_capacity(settings.thread_count);
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let framebuffer = Arc::new(Mutex::new(std::mem::take(&mut state.sums)));
+
+        // Statistics only; the workers never read them back, so they cannot
+        // affect the image.
+        let counters = Arc::new(PassCounters {
+            tiles_completed: AtomicUsize::new(0),
+            rows_completed: AtomicUsize::new(0),
+            band_tiles_remaining: (0..band_count)
+                .map(|_| AtomicUsize::new(nx.div_ceil(settings.tile_size)))
+                .collect(),
+            samples: AtomicU64::new(0),
+            rays: AtomicU64::new(0),
+        });
+        // Each worker holds a sender, so the channel disconnects once they
+        // have all finished.
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+
+        for _ in 0..settings.thread_count {
+            let local_world = arc_world.clone();
+            let local_tiles = arc_tiles.clone();
+            let local_next_tile = next_tile.clone();
+            let local_framebuffer = framebuffer.clone();
+            let local_counters = counters.clone();
+            let local_done = done_sender.clone();
+
+            let thd = std::thread::spawn(move || {
+                let _done = local_done;
+                let mut cols: Vec<Vec3> = Vec::new();
+                loop {
+                    let tile_index = local_next_tile.fetch_add(1, Ordering::Relaxed);
+                    let tile = match local_tiles.get(tile_index) {
+                        Some(tile) => tile,
+                        None => break,
+                    };
+
+                    // Pixels of a tile belong to one worker for the whole pass,
+                    // so it can keep accumulating onto the running sums.
+                    cols.clear();
+                    {
+                        let sums = local_framebuffer.lock().unwrap();
+                        for row in tile.y0..tile.y1 {
+                            let start = row * nx + tile.x0;
+                            cols.extend_from_slice(&sums[start..start + tile.width()]);
+                        }
+                    }
+
+                    let mut rays = 0u64;
+                    for (row, row_cols) in (tile.y0..tile.y1).zip(cols.chunks_mut(tile.width())) {
+                        // Rows count down from the top, the camera's v counts up.
+                        let yd = (ny - 1 - row) as f32;
+                        // Camera rays through neighbouring pixels are traced as
+                        // a packet, then each path carries on by itself.
+                        for (group, group_cols) in row_cols.chunks_mut(PACKET_SIZE).enumerate() {
+                            let x0 = tile.x0 + group * PACKET_SIZE;
+                            let active = (1 << group_cols.len()) - 1;
+                            for sample in first_sample..first_sample + pass_samples {
+                                let mut rnds: [Random; PACKET_SIZE] = std::array::from_fn(|lane| {
+                                    let pixel_index = (row * nx + x0 + lane) as u64;
+                                    Random::create_with_seed(rng::mix_seed(&[
+                                        seed,
+                                        pixel_index,
+                                        u64::from(sample),
+                                    ]))
+                                });
+                                let camera_rays = std::array::from_fn(|lane| {
+                                    if lane >= group_cols.len() {
+                                        return Ray::default();
+                                    }
+                                    let rnd = &mut rnds[lane];
+                                    let u = ((x0 + lane) as f32 + rnd.gen()) / nxd;
+                                    let v = (yd + rnd.gen()) / nyd;
+                                    camera.get_ray(u, v, rnd)
+                                });
+                                let packet = RayPacket::new(camera_rays);
+                                let records =
+                                    local_world.hit_packet(&packet, active, MIN_THING, MAX_THING);
+                                for ((col, record), (ray, rnd)) in group_cols
+                                    .iter_mut()
+                                    .zip(records)
+                                    .zip(packet.rays.iter().zip(rnds.iter_mut()))
+                                {
+                                    rays += 1;
+                                    *col += &shade(
+                                        ray,
+                                        record,
+                                        local_world.as_ref(),
+                                        rnd,
+                                        1,
+                                        max_depth,
+                                        &mut rays,
+                                    );
+                                }
+                            }
+                        }
+                    }
+
+                    {
+                        let mut sums = local_framebuffer.lock().unwrap();
+                        for (offset, row) in cols.chunks(tile.width()).enumerate() {
+                            let start = (tile.y0 + offset) * nx + tile.x0;
+                            sums[start..start + tile.width()].copy_from_slice(row);
+                        }
+                    }
+
+                    local_counters.samples.fetch_add(
+                        cols.len() as u64 * u64::from(pass_samples),
+                        Ordering::Relaxed,
+                    );
+                    local_counters.rays.fetch_add(rays, Ordering::Relaxed);
+                    local_counters
+                        .tiles_completed
+                        .fetch_add(1, Ordering::Relaxed);
+                    let band = &local_counters.band_tiles_remaining[tile.y0 / tile_size];
+                    if band.fetch_sub(1, Ordering::Relaxed) == 1 {
+                        local_counters
+                            .rows_completed
+                            .fetch_add(tile.y1 - tile.y0, Ordering::Relaxed);
+                    }
+                }
+            });
+
+            workers.push(thd);
+        }
+
+        drop(done_sender);
+
+        let report = |counters: &PassCounters| {
+            let pass_samples_done = counters.samples.load(Ordering::Relaxed);
+            Progress {
+                pass: state.passes_completed + 1,
+                pass_count,
+                tiles_completed: counters.tiles_completed.load(Ordering::Relaxed),
+                tile_count,
+                rows_completed: counters.rows_completed.load(Ordering::Relaxed),
+                row_count: ny,
+                samples_completed: u64::from(first_sample) * pixel_count + pass_samples_done,
+                samples_total: u64::from(settings.samples_per_pixel) * pixel_count,
+                samples_rendered: samples_rendered + pass_samples_done,
+                rays_traced: rays_traced + counters.rays.load(Ordering::Relaxed),
+                elapsed: start_time.elapsed(),
+            }
+        };
+        while let Err(RecvTimeoutError::Timeout) = done_receiver.recv_timeout(PROGRESS_INTERVAL) {
+            on_progress(&report(&counters));
+        }
+
+        for waiter in workers {
+            waiter.join().unwrap();
+        }
+        let progress = report(&counters);
+        samples_rendered = progress.samples_rendered;
+        rays_traced = progress.rays_traced;
+        on_progress(&progress);
+
+        state.sums = match Arc::try_unwrap(framebuffer) {
+            Ok(sums) => sums.into_inner().unwrap(),
+            Err(_) => unreachable!("all workers have been joined"),
+        };
+        for count in state.sample_counts.iter_mut() {
+            *count += pass_samples;
+        }
+        state.passes_completed += 1;
+        first_sample += pass_samples;
+        on_pass(state);
+    }
+}
+
+struct PassCounters {
+    tiles_completed: AtomicUsize,
+    rows_completed: AtomicUsize,
+    // Tiles left in each band of rows; a band's rows are complete at zero.
+    band_tiles_remaining: Vec<AtomicUsize>,
+    samples: AtomicU64,
+    rays: AtomicU64,
+}
+
+// `rays` counts every ray traced, for the progress statistics.
+fn colour(
+    ray: &Ray,
+    world: &World,
+    rnd: &mut Random,
+    depth: i32,
+    max_depth: i32,
+    rays: &mut u64,
+) -> Vec3 {
+    *rays += 1;
+    let record = world.hit(ray, MIN_THING, MAX_THING);
+    shade(ray, record, world, rnd, depth, max_depth, rays)
+}
+
+// The light coming back along `ray`, given what it hit.
+fn shade(
+    ray: &Ray,
+    record: Option<HitRecord<'_>>,
+    world: &World,
+    rnd: &mut Random,
+    depth: i32,
+    max_depth: i32,
+    rays: &mut u64,
+) -> Vec3 {
+    match record {
+        None => {
+            // Render "Sky"
+            let direction = ray.direction.make_normalised();
+            let t = 0.5 * (direction.y() + 1.0);
+
+            (&Vec3::from(1.0, 1.0, 1.0) * (1.0 - t)) + (&Vec3::from(0.5, 0.7, 1.0) * t)
+        }
+        Some(rec) => {
+            // Bounces happen at the instant the camera ray was sent.
+            let mut scattered = Ray {
+                time: ray.time,
+                medium_sample: rnd.gen_separate(),
+                ..Ray::default()
+            };
+            let mut attenuation = Vec3::default();
+            let emitted = rec.material.emitted(&rec);
+            if depth < max_depth
+                && rec
+                    .material
+                    .scatter(ray, &rec, rnd, &mut attenuation, &mut scattered)
+            {
+                emitted
+                    + attenuation.direct_product(&colour(
+                        &scattered,
+                        world,
+                        rnd,
+                        depth + 1,
+                        max_depth,
+                        rays,
+                    ))
+            } else {
+                emitted
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::hitable::{Hitable, Sphere};
+    use crate::material::{Dielectric, Lambertian, Material, Metal};
+
+    fn small_settings() -> RenderSettings {
+        RenderSettings {
+            width: 13,
+            height: 7,
+            samples_per_pixel: 6,
+            pass_samples: 2,
+            max_depth: 8,
+            thread_count: 1,
+            tile_size: 4,
+            ..RenderSettings::default()
+        }
+    }
+
+    // A few spheres of each material, so that paths scatter, reflect and
+    // refract.
+    fn small_scene() -> (Camera, World) {
+        let sphere = |x: f32, y: f32, radius: f32, material: Box<dyn Material>| {
+            Box::new(Sphere {
+                center: Vec3::from(x, y, -1.0),
+                radius,
+                material,
+            }) as Box<dyn Hitable>
+        };
+        let mut hitables = vec![
+            sphere(
+                0.0,
+                -100.5,
+                100.0,
+                Box::new(Lambertian::with_albedo(Vec3::from(0.8, 0.8, 0.0))),
+            ),
+            sphere(
+                -1.0,
+                0.0,
+                0.5,
+                Box::new(Dielectric::with_refraction_index(1.5)),
+            ),
+            sphere(
+                0.0,
+                0.0,
+                0.5,
+                Box::new(Lambertian::with_albedo(Vec3::from(0.1, 0.2, 0.5))),
+            ),
+            sphere(
+                1.0,
+                0.0,
+                0.5,
+                Box::new(Metal::with_albedo(Vec3::from(0.8, 0.6, 0.2))),
+            ),
+        ];
+        let camera = CameraSettings {
+            look_from: Vec3::from(0.0, 0.5, 2.0),
+            look_at: Vec3::from(0.0, 0.0, -1.0),
+            ..CameraSettings::default()
+        }
+        .build(small_settings().aspect_ratio());
+        let world = World::build(&mut hitables, &mut Random::create_with_seed(0));
+        (camera, world)
+    }
+
+    fn pixel_bits(pixels: &[Vec3]) -> Vec<[u32; 3]> {
+        pixels
+            .iter()
+            .map(|p| [p.r().to_bits(), p.g().to_bits(), p.b().to_bits()])
+            .collect()
+    }
+
+    fn render_bits(settings: &RenderSettings) -> Vec<[u32; 3]> {
+        let (camera, world) = small_scene();
+        pixel_bits(&render(camera, world, settings).pixels)
+    }
+
+    #[test]
+    fn images_do_not_depend_on_scheduling() {
+        let reference = render_bits(&small_settings());
+        let variations = [
+            RenderSettings {
+                thread_count: 3,
+                ..small_settings()
+            },
+            RenderSettings {
+                tile_size: 5,
+                tile_order: TileOrder::Scanline,
+                ..small_settings()
+            },
+            RenderSettings {
+                pass_samples: 6,
+                thread_count: 2,
+                ..small_settings()
+            },
+            RenderSettings {
+                pass_samples: 1,
+                tile_size: 64,
+                ..small_settings()
+            },
+        ];
+        for settings in variations.iter() {
+            assert!(render_bits(settings) == reference);
+        }
+        // A different seed does change the image.
+        let reseeded = RenderSettings {
+            seed: 7,
+            ..small_settings()
+        };
+        assert!(render_bits(&reseeded) != reference);
+    }
+
+    #[test]
+    fn resumed_renders_match_uninterrupted_ones() {
+        let reference = render_bits(&small_settings());
+
+        // Stop after half the samples, save, and carry on from the file with
+        // other scheduling settings.
+        let half = RenderSettings {
+            samples_per_pixel: 3,
+            pass_samples: 1,
+            ..small_settings()
+        };
+        let mut state = Checkpoint::new(half.width, half.height, half.seed, 0);
+        let (camera, world) = small_scene();
+        render_progressive(camera, world, &half, &mut state, &mut |_| {}, &mut |_| {});
+        let path =
+            std::env::temp_dir().join(format!("raytracer-resume-{}.ckpt", std::process::id()));
+        state.save(&path).unwrap();
+        let mut state = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rest = RenderSettings {
+            thread_count: 2,
+            tile_size: 3,
+            ..small_settings()
+        };
+        let (camera, world) = small_scene();
+        render_progressive(camera, world, &rest, &mut state, &mut |_| {}, &mut |_| {});
+        assert!(state.sample_counts.iter().all(|&count| count == 6));
+        assert!(pixel_bits(&state.average()) == reference);
+    }
+
+    #[test]
+    #[should_panic(expected = "thread_count must be at least 1")]
+    fn rejects_zero_threads() {
+        let (camera, world) = small_scene();
+        let settings = RenderSettings {
+            thread_count: 0,
+            ..small_settings()
+        };
+        render(camera, world, &settings);
+    }
+
+    #[test]
+    #[should_panic(expected = "tile_size must be at least 1")]
+    fn rejects_empty_tiles() {
+        let (camera, world) = small_scene();
+        let settings = RenderSettings {
+            tile_size: 0,
+            ..small_settings()
+        };
+        render(camera, world, &settings);
+    }
+
+    #[test]
+    #[should_panic(expected = "pass_samples must be at least 1")]
+    fn rejects_empty_passes() {
+        let (camera, world) = small_scene();
+        let settings = RenderSettings {
+            pass_samples: 0,
+            ..small_settings()
+        };
+        render(camera, world, &settings);
+    }
+}
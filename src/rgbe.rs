@@ -0,0 +1,171 @@
+// Radiance RGBE (.hdr) writer, following Greg Ward's reference rgbe.c.
+
+use crate::vec3::Vec3;
+
+use std::io::{self, Write};
+
+const MIN_RUN_LENGTH: usize = 4;
+
+// The largest value an RGBE pixel holds: a mantissa of 255 with the
+// exponent byte at 255.
+const MAX_RGBE: f32 = 255.0 / 256.0 * (1u128 << 127) as f32;
+
+pub fn write_hdr(
+    out: &mut dyn Write,
+    width: usize,
+    height: usize,
+    pixels: &[Vec3],
+) -> io::Result<()> {
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut scanline = Vec::with_capacity(width * 4);
+    for row in pixels.chunks(width) {
+        scanline.clear();
+        for col in row {
+            scanline.extend_from_slice(&to_rgbe(col));
+        }
+
+        // Run-length encoding is only defined for these widths; anything
+        // else is written flat.
+        if !(8..0x8000).contains(&width) {
+            out.write_all(&scanline)?;
+            continue;
+        }
+
+        out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        let mut component = Vec::with_capacity(width);
+        for channel in 0..4 {
+            component.clear();
+            component.extend(scanline.iter().skip(channel).step_by(4));
+            write_rle_bytes(out, &component)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn to_rgbe(col: &Vec3) -> [u8; 4] {
+    let channel = |value: f32| {
+        if value.is_finite() {
+            value.clamp(0.0, MAX_RGBE)
+        } else {
+            0.0
+        }
+    };
+    let (r, g, b) = (channel(*col.r()), channel(*col.g()), channel(*col.b()));
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    let (mantissa, exponent) = frexp(v);
+    let scale = mantissa * 256.0 / v;
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+// Splits a positive value into a mantissa in [0.5, 1) and a power of two.
+fn frexp(value: f32) -> (f32, i32) {
+    let mut exponent = value.log2().floor() as i32 + 1;
+    let mut mantissa = value / 2.0f32.powi(exponent);
+    // log2 can be off by one ulp near powers of two.
+    if mantissa >= 1.0 {
+        mantissa *= 0.5;
+        exponent += 1;
+    } else if mantissa < 0.5 {
+        mantissa *= 2.0;
+        exponent -= 1;
+    }
+    (mantissa, exponent)
+}
+
+fn write_rle_bytes(out: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    let mut current = 0;
+    while current < data.len() {
+        // Look for the next run of at least MIN_RUN_LENGTH equal bytes.
+        let mut run_start = current;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN_LENGTH && run_start < data.len() {
+            run_start += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while run_start + run_count < data.len()
+                && run_count < 127
+                && data[run_start] == data[run_start + run_count]
+            {
+                run_count += 1;
+            }
+        }
+
+        // A short run just before the long one is still worth encoding.
+        if old_run_count > 1 && old_run_count == run_start - current {
+            out.write_all(&[128 + old_run_count as u8, data[current]])?;
+            current = run_start;
+        }
+
+        while current < run_start {
+            let count = (run_start - current).min(128);
+            out.write_all(&[count as u8])?;
+            out.write_all(&data[current..current + count])?;
+            current += count;
+        }
+
+        if run_count >= MIN_RUN_LENGTH {
+            out.write_all(&[128 + run_count as u8, data[run_start]])?;
+            current += run_count;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ward's decoding, from the middle of each mantissa step.
+    fn from_rgbe(rgbe: [u8; 4]) -> [f32; 3] {
+        if rgbe[3] == 0 {
+            return [0.0; 3];
+        }
+        let scale = 2.0f32.powi(i32::from(rgbe[3]) - 136);
+        [0, 1, 2].map(|i| (f32::from(rgbe[i]) + 0.5) * scale)
+    }
+
+    #[test]
+    fn zero_and_invalid_values_are_black() {
+        assert_eq!(to_rgbe(&Vec3::from(0.0, 0.0, 0.0)), [0; 4]);
+        assert_eq!(to_rgbe(&Vec3::from(1e-40, 0.0, 0.0)), [0; 4]);
+        assert_eq!(to_rgbe(&Vec3::from(-1.0, f32::NAN, f32::INFINITY)), [0; 4]);
+    }
+
+    #[test]
+    fn packs_shared_exponent() {
+        assert_eq!(to_rgbe(&Vec3::from(1.0, 1.0, 1.0)), [128, 128, 128, 129]);
+        assert_eq!(to_rgbe(&Vec3::from(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        for value in [1e-20, 0.3, 7.0, 1234.5, 1e30] {
+            let decoded = from_rgbe(to_rgbe(&Vec3::from(value, value * 0.25, 0.0)));
+            assert!((decoded[0] - value).abs() <= value / 128.0);
+            assert!((decoded[1] - value * 0.25).abs() <= value / 128.0);
+        }
+    }
+
+    #[test]
+    fn clamps_values_beyond_the_largest_exponent() {
+        let rgbe = to_rgbe(&Vec3::from(f32::MAX, 1e38, 0.0));
+        assert_eq!(rgbe[3], 255);
+        assert!(rgbe[0] >= 254);
+        let decoded = from_rgbe(rgbe);
+        assert!(decoded[0] > 1.6e38 && decoded[0].is_finite());
+        assert!((decoded[1] - 1e38).abs() <= 1e38 / 64.0);
+    }
+}
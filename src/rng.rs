@@ -7,6 +7,8 @@ use rand::*;
 pub struct Random {
     rng: SmallRng,
     dist: Uniform<f32>,
+    seed: u64,
+    separate_draws: u64,
 }
 
 impl Random {
@@ -14,10 +16,39 @@ impl Random {
         Random {
             rng: SmallRng::seed_from_u64(seed),
             dist: Uniform::new(0.0f32, 1.0f32),
+            seed,
+            separate_draws: 0,
         }
     }
 
     pub fn gen(&mut self) -> f32 {
         self.dist.sample(&mut self.rng)
     }
+
+    // A number in (0, 1] from a second stream of the same seed. Values that
+    // only some scenes use come from here, so drawing them does not shift
+    // the numbers `gen` returns and the images of other scenes stay the same.
+    pub fn gen_separate(&mut self) -> f32 {
+        self.separate_draws += 1;
+        unit_from_hash(mix_seed(&[self.seed, self.separate_draws]))
+    }
+}
+
+// A number in (0, 1] from the top 24 bits of a hash, which fill an f32
+// mantissa exactly.
+pub fn unit_from_hash(hash: u64) -> f32 {
+    ((hash >> 40) + 1) as f32 / (1u64 << 24) as f32
+}
+
+// Combines values into a well mixed 64-bit seed (splitmix64 finaliser), so
+// neighbouring inputs give unrelated random streams.
+pub fn mix_seed(values: &[u64]) -> u64 {
+    let mut hash = 0x9e37_79b9_7f4a_7c15u64;
+    for value in values {
+        let mut z = hash ^ value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash = z ^ (z >> 31);
+    }
+    hash
 }
@@ -0,0 +1,1711 @@
+// Text scene description format.
+//
+//     # Comments run to the end of the line.
+//     settings { width 1920 height 1080 samples 256 max_depth 20 seed 7 }
+//     camera {
+//         look_from 13 2 3
+//         look_at 0 0 0
+//         up 0 1 0
+//         vfov 20
+//         aperture 0.1
+//         focus_distance 10
+//         shutter_open 0
+//         shutter_close 1
+//     }
+//     material ground lambertian { albedo 0.5 0.5 0.5 }
+//     material steel metal { albedo 0.7 0.6 0.5 }
+//     material glass dielectric { refraction_index 1.5 }
+//     material lamp light { emission 4 4 4 }
+//     sphere { center 0 1 0 radius 1 material glass }
+//     moving_sphere { center0 0 1 0 center1 0 1.5 0 time0 0 time1 1 radius 0.5 material steel }
+//     rect { plane xz min 213 227 max 343 332 offset 554 material lamp }
+//     plane { point 0 0 0 normal 0 1 0 material ground }
+//     quad { corner 0 0 0 u 1 0 0 v 0 1 0 material steel }
+//     box { min 0 0 0 max 1 2 1 material ground }
+//     disk { center 0 0 0 normal 0 1 0 radius 2 inner_radius 1 material steel }
+//     cylinder { base 0 0 0 top 0 2 0 radius 0.5 capped material steel }
+//     cone { base 0 0 0 apex 0 1 0 radius 0.5 capped material steel }
+//     paraboloid { vertex 0 0 0 top 0 1 0 radius 0.5 material steel }
+//     sdf {
+//         material steel
+//         smooth_union {
+//             smoothness 0.2
+//             torus { center 0 1 0 major_radius 1 minor_radius 0.25 }
+//             capsule { a 0 0 0 b 0 2 0 radius 0.3 }
+//         }
+//     }
+//     obj { file "models/teapot.obj" material steel }
+//     ply { file "scans/statue.ply" }
+//     object crate { box { min 0 0 0 max 1 1 1 material ground } }
+//     instance { object crate scale 2 rotate_y 15 translate 3 0 -1 }
+//     difference {
+//         box { min -1 -1 -1 max 1 1 1 material steel }
+//         cylinder { base 0 -2 0 top 0 2 0 radius 0.5 capped material steel }
+//     }
+//     object haze { box { min -5 0 -5 max 5 3 5 material ground } }
+//     medium { object haze density 0.05 albedo 1 1 1 }
+//     instance {
+//         object crate
+//         keyframe 0 { translate -1 0 0 }
+//         keyframe 1 { translate 1 0 0 rotate_y 90 }
+//     }
+//
+// Model paths are relative to the scene file. Without a material OBJ models
+// use their MTL materials and PLY meshes a Lambertian, white if the file
+// has vertex colours so that those become the albedo.
+//
+// Rects, quads and planes are single-sided: their normal faces whichever
+// side a ray comes from. Box faces point outwards. A plane extends without
+// limit and is kept out of the BVH.
+// A disk with an inner_radius is an annulus. Cylinders, cones and
+// paraboloids are open unless `capped`.
+//
+// An object groups shapes without adding them to the scene; each instance
+// places it with a transform applied as scale (one factor or three), then
+// rotate_x, rotate_y, rotate_z (in degrees), then translate. Instances share
+// the object's geometry. An instance can instead list keyframes, each a
+// time followed by its own transform; rays see the transform interpolated
+// to their time, and the first or last one outside the keyframes.
+//
+// An sdf is built from one distance function block: torus, rounded_box
+// (half_extents including the rounding radius), capsule (a sphere when a and
+// b are the same point), mandelbulb (power 8 and 12 iterations by default),
+// or smooth_union and smooth_subtraction, which combine two or more of these
+// from the left with a fillet of the given smoothness.
+//
+// union, intersection and difference combine closed shapes as solids;
+// difference carves all later shapes out of the first. Each surface keeps
+// its own material.
+//
+// A medium fills the inside of an object, which should be closed and convex,
+// with fog of the given density (per unit length) and albedo (default
+// white). The object's own material is not rendered.
+//
+// Motion blur needs the camera's shutter_close after its shutter_open; rays
+// then get times spread between the two. A moving sphere travels from
+// center0 at time0 (default 0) to center1 at time1 (default 1).
+//
+// Every block and property is optional except for the properties an object
+// or material needs to be built. Settings and camera values replace the
+// renderer defaults, command-line options still take precedence.
+
+use crate::camera::CameraSettings;
+use crate::csg::{Csg, CsgOperation};
+use crate::hitable::*;
+use crate::material::*;
+use crate::medium::ConstantMedium;
+use crate::obj;
+use crate::ply;
+use crate::quadrics::*;
+use crate::render::RenderSettings;
+use crate::rng::Random;
+use crate::sdf::{self, DistanceFunction, SdfShape};
+use crate::shapes::*;
+use crate::transform::{AnimatedTransformed, Keyframe, Transformed};
+use crate::vec3::Vec3;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub struct Scene {
+    pub hitables: Vec<Box<dyn Hitable>>,
+    pub settings: SceneSettings,
+    // The model files (OBJ, MTL and PLY) the scene was built from.
+    pub files: Vec<PathBuf>,
+    // Problems in those files that were worked around, for the caller to
+    // report.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct SceneSettings {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<u32>,
+    pub max_depth: Option<i32>,
+    pub seed: Option<u64>,
+    pub look_from: Option<Vec3>,
+    pub look_at: Option<Vec3>,
+    pub up: Option<Vec3>,
+    pub vertical_fov_degrees: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_distance: Option<f32>,
+    pub shutter_open: Option<f32>,
+    pub shutter_close: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+impl SceneSettings {
+    pub fn apply(&self, render: &mut RenderSettings, camera: &mut CameraSettings) {
+        fn set<T: Copy>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+
+        set(&mut render.width, self.width);
+        set(&mut render.height, self.height);
+        set(&mut render.samples_per_pixel, self.samples_per_pixel);
+        set(&mut render.max_depth, self.max_depth);
+        set(&mut render.seed, self.seed);
+        set(&mut camera.look_from, self.look_from);
+        set(&mut camera.look_at, self.look_at);
+        set(&mut camera.up, self.up);
+        set(&mut camera.vertical_fov_degrees, self.vertical_fov_degrees);
+        set(&mut camera.aperture, self.aperture);
+        set(&mut camera.focus_distance, self.focus_distance);
+        set(&mut camera.shutter_open, self.shutter_open);
+        set(&mut camera.shutter_close, self.shutter_close);
+    }
+}
+
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene_in(&source, directory)
+}
+
+// Files referenced by the scene are looked up relative to the working
+// directory.
+pub fn parse_scene(source: &str) -> Result<Scene, SceneError> {
+    parse_scene_in(source, Path::new(""))
+}
+
+fn parse_scene_in(source: &str, directory: &Path) -> Result<Scene, SceneError> {
+    let tokens = tokenise(source)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        materials: HashMap::new(),
+        objects: HashMap::new(),
+        directory: directory.to_path_buf(),
+        files: Vec::new(),
+        warnings: Vec::new(),
+    };
+    parser.parse()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Number(String),
+    Str(String),
+    OpenBrace,
+    CloseBrace,
+    End,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl TokenKind {
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Word(word) => format!("'{}'", word),
+            TokenKind::Number(text) => format!("number {}", text),
+            TokenKind::Str(value) => format!("string \"{}\"", value),
+            TokenKind::OpenBrace => "'{'".to_string(),
+            TokenKind::CloseBrace => "'}'".to_string(),
+            TokenKind::End => "end of file".to_string(),
+        }
+    }
+}
+
+fn parse_error<T>(line: usize, column: usize, message: String) -> Result<T, SceneError> {
+    Err(SceneError::Parse {
+        line,
+        column,
+        message,
+    })
+}
+
+fn tokenise(source: &str) -> Result<Vec<Token>, SceneError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    let mut column = 1;
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut advance = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            c
+        };
+
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                advance(&mut chars);
+                continue;
+            }
+            '#' => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    advance(&mut chars);
+                }
+                continue;
+            }
+            '{' => {
+                advance(&mut chars);
+                TokenKind::OpenBrace
+            }
+            '}' => {
+                advance(&mut chars);
+                TokenKind::CloseBrace
+            }
+            '"' => {
+                advance(&mut chars);
+                let mut value = String::new();
+                loop {
+                    match advance(&mut chars) {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return parse_error(
+                                start_line,
+                                start_column,
+                                "unterminated string".to_string(),
+                            )
+                        }
+                        Some(c) => value.push(c),
+                    }
+                }
+                TokenKind::Str(value)
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.' {
+                        text.push(c);
+                        advance(&mut chars);
+                    } else {
+                        break;
+                    }
+                }
+                match text.parse::<f32>() {
+                    Ok(value) if value.is_finite() => TokenKind::Number(text),
+                    _ => {
+                        return parse_error(
+                            start_line,
+                            start_column,
+                            format!("invalid number '{}'", text),
+                        )
+                    }
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        word.push(c);
+                        advance(&mut chars);
+                    } else {
+                        break;
+                    }
+                }
+                TokenKind::Word(word)
+            }
+            _ => {
+                return parse_error(
+                    start_line,
+                    start_column,
+                    format!("unexpected character '{}'", c),
+                )
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            line: start_line,
+            column: start_column,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        line,
+        column,
+    });
+    Ok(tokens)
+}
+
+const SHAPE_BLOCKS: &str = "sphere, moving_sphere, rect, plane, quad, box, disk, cylinder, cone, \
+                            paraboloid, sdf, union, intersection, difference, obj, ply, \
+                            instance or medium";
+
+const DISTANCE_BLOCKS: [&str; 6] = [
+    "torus",
+    "rounded_box",
+    "capsule",
+    "mandelbulb",
+    "smooth_union",
+    "smooth_subtraction",
+];
+
+#[derive(Copy, Clone)]
+enum MaterialDesc {
+    Lambertian(Vec3),
+    Metal(Vec3),
+    Dielectric(f32),
+    Light(Vec3),
+}
+
+impl MaterialDesc {
+    fn build(&self) -> Box<dyn Material> {
+        match *self {
+            MaterialDesc::Lambertian(albedo) => Box::new(Lambertian::with_albedo(albedo)),
+            MaterialDesc::Metal(albedo) => Box::new(Metal::with_albedo(albedo)),
+            MaterialDesc::Dielectric(index) => Box::new(Dielectric::with_refraction_index(index)),
+            MaterialDesc::Light(emission) => Box::new(DiffuseLight::with_emission(emission)),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    materials: HashMap<String, MaterialDesc>,
+    objects: HashMap<String, Arc<dyn Hitable>>,
+    directory: PathBuf,
+    files: Vec<PathBuf>,
+    warnings: Vec<String>,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Scene, SceneError> {
+        let mut scene = Scene {
+            hitables: Vec::new(),
+            settings: SceneSettings::default(),
+            files: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        loop {
+            let token = self.next();
+            let word = match &token.kind {
+                TokenKind::End => break,
+                TokenKind::Word(word) => word.clone(),
+                other => {
+                    return parse_error(
+                        token.line,
+                        token.column,
+                        format!("expected a block name, found {}", other.describe()),
+                    )
+                }
+            };
+
+            match word.as_str() {
+                "settings" => self.parse_settings(&mut scene.settings)?,
+                "camera" => self.parse_camera(&mut scene.settings)?,
+                "material" => self.parse_material()?,
+                "object" => self.parse_object()?,
+                _ => match self.parse_shape(&word, &token)? {
+                    Some(hitables) => scene.hitables.extend(hitables),
+                    None => {
+                        return parse_error(
+                            token.line,
+                            token.column,
+                            format!(
+                            "unknown block '{}', expected settings, camera, material, object or {}",
+                            word, SHAPE_BLOCKS
+                        ),
+                        )
+                    }
+                },
+            }
+        }
+
+        scene.files = std::mem::take(&mut self.files);
+        scene.warnings = std::mem::take(&mut self.warnings);
+        Ok(scene)
+    }
+
+    // None if `word` does not start a shape block.
+    fn parse_shape(
+        &mut self,
+        word: &str,
+        token: &Token,
+    ) -> Result<Option<Vec<Box<dyn Hitable>>>, SceneError> {
+        Ok(Some(match word {
+            "sphere" => vec![self.parse_sphere(token)?],
+            "moving_sphere" => vec![self.parse_moving_sphere(token)?],
+            "rect" => vec![self.parse_rect(token)?],
+            "plane" => vec![self.parse_plane(token)?],
+            "quad" => vec![self.parse_quad(token)?],
+            "box" => vec![self.parse_box(token)?],
+            "disk" => vec![self.parse_disk(token)?],
+            "cylinder" | "cone" | "paraboloid" => vec![self.parse_quadric(word, token)?],
+            "sdf" => vec![self.parse_sdf(token)?],
+            "union" => vec![self.parse_csg(CsgOperation::Union, token)?],
+            "intersection" => vec![self.parse_csg(CsgOperation::Intersection, token)?],
+            "difference" => vec![self.parse_csg(CsgOperation::Difference, token)?],
+            "obj" => self.parse_obj(token)?,
+            "ply" => self.parse_ply(token)?,
+            "instance" => vec![self.parse_instance(token)?],
+            "medium" => vec![self.parse_medium(token)?],
+            _ => return Ok(None),
+        }))
+    }
+
+    fn parse_object(&mut self) -> Result<(), SceneError> {
+        let name_token = self.next();
+        let name = match &name_token.kind {
+            TokenKind::Word(name) | TokenKind::Str(name) => name.clone(),
+            other => {
+                return parse_error(
+                    name_token.line,
+                    name_token.column,
+                    format!("expected an object name, found {}", other.describe()),
+                )
+            }
+        };
+        if self.objects.contains_key(&name) {
+            return parse_error(
+                name_token.line,
+                name_token.column,
+                format!("object '{}' is already defined", name),
+            );
+        }
+
+        let mut hitables: Vec<Box<dyn Hitable>> =
+            self.parse_shape_groups()?.into_iter().flatten().collect();
+        let object: Arc<dyn Hitable> = match hitables.len() {
+            0 => {
+                return parse_error(
+                    name_token.line,
+                    name_token.column,
+                    format!("object '{}' does not contain any shapes", name),
+                )
+            }
+            1 => Arc::from(hitables.remove(0)),
+            // The split axes only affect speed, so any fixed seed will do.
+            _ => Arc::new(BvhTree::build(
+                &mut hitables,
+                &mut Random::create_with_seed(0),
+            )),
+        };
+        self.objects.insert(name, object);
+        Ok(())
+    }
+
+    // Parses "{ shape... }", keeping the hitables of each shape block
+    // together.
+    fn parse_shape_groups(&mut self) -> Result<Vec<Vec<Box<dyn Hitable>>>, SceneError> {
+        self.expect(TokenKind::OpenBrace)?;
+        let mut groups = Vec::new();
+        loop {
+            let token = self.next();
+            let word = match &token.kind {
+                TokenKind::CloseBrace => return Ok(groups),
+                TokenKind::Word(word) => word.clone(),
+                other => {
+                    return parse_error(
+                        token.line,
+                        token.column,
+                        format!("expected a shape or '}}', found {}", other.describe()),
+                    )
+                }
+            };
+            match self.parse_shape(&word, &token)? {
+                Some(shapes) => groups.push(shapes),
+                None => {
+                    return parse_error(
+                        token.line,
+                        token.column,
+                        format!("unknown shape '{}', expected {}", word, SHAPE_BLOCKS),
+                    )
+                }
+            }
+        }
+    }
+
+    // Union and intersection combine all their shapes; difference carves
+    // every later shape out of the first. Models with several parts, such as
+    // an OBJ file, count as one shape.
+    fn parse_csg(
+        &mut self,
+        operation: CsgOperation,
+        start: &Token,
+    ) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut operands = Vec::new();
+        for mut group in self.parse_shape_groups()? {
+            operands.push(match group.len() {
+                0 => continue,
+                1 => group.remove(0),
+                // As for objects, the seed only affects speed.
+                _ => Box::new(BvhTree::build(&mut group, &mut Random::create_with_seed(0)))
+                    as Box<dyn Hitable>,
+            });
+        }
+        if operands.len() < 2 {
+            return parse_error(
+                start.line,
+                start.column,
+                "a union, intersection or difference needs at least two shapes".to_string(),
+            );
+        }
+        let mut operands = operands.into_iter();
+        let first = operands.next().unwrap();
+        Ok(operands.fold(first, |a, b| Box::new(Csg::new(operation, a, b))))
+    }
+
+    fn parse_instance(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut object = None;
+        let mut pose = Keyframe::default();
+        let mut posed = false;
+        let mut keyframes = Vec::new();
+        self.parse_block_repeating(&["keyframe"], |parser, key| {
+            match key.as_str() {
+                "object" => object = Some(parser.object_reference()?),
+                "keyframe" => {
+                    let mut keyframe = Keyframe {
+                        time: parser.number()?,
+                        ..Keyframe::default()
+                    };
+                    parser.parse_block(|parser, key| parser.pose_property(key, &mut keyframe))?;
+                    keyframes.push(keyframe);
+                }
+                _ => {
+                    if !parser.pose_property(key, &mut pose)? {
+                        return Ok(false);
+                    }
+                    posed = true;
+                }
+            }
+            Ok(true)
+        })?;
+
+        let object = self.require(object, "object", start)?;
+        if keyframes.is_empty() {
+            return match Transformed::new(object, pose.world_matrix()) {
+                Some(instance) => Ok(Box::new(instance)),
+                None => parse_error(
+                    start.line,
+                    start.column,
+                    "the instance transform cannot be inverted, check for a scale of zero"
+                        .to_string(),
+                ),
+            };
+        }
+        if posed {
+            return parse_error(
+                start.line,
+                start.column,
+                "an instance with keyframes takes its transform from them, move scale, rotate \
+                 and translate into the keyframes"
+                    .to_string(),
+            );
+        }
+        match AnimatedTransformed::new(object, keyframes) {
+            Some(instance) => Ok(Box::new(instance)),
+            None => parse_error(
+                start.line,
+                start.column,
+                "a keyframe transform cannot be inverted, check for a scale of zero".to_string(),
+            ),
+        }
+    }
+
+    fn parse_medium(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut boundary = None;
+        let mut density = None;
+        let mut albedo = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "object" => boundary = Some(parser.object_reference()?),
+                "density" => density = Some(parser.number()?),
+                "albedo" => albedo = Some(parser.vec3()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        let boundary = self.require(boundary, "object", start)?;
+        let density = self.require(density, "density", start)?;
+        if !(density > 0.0 && density.is_finite()) {
+            return parse_error(
+                start.line,
+                start.column,
+                format!("the medium density must be positive, got {}", density),
+            );
+        }
+        Ok(Box::new(ConstantMedium::new(
+            boundary,
+            density,
+            albedo.unwrap_or_else(|| Vec3::from(1.0, 1.0, 1.0)),
+        )))
+    }
+
+    // The scale, rotate_x, rotate_y, rotate_z and translate properties of an
+    // instance or keyframe.
+    fn pose_property(&mut self, key: &str, pose: &mut Keyframe) -> Result<bool, SceneError> {
+        match key {
+            "scale" => {
+                let x = self.number()?;
+                pose.scale = if self.next_is_number() {
+                    Vec3::from(x, self.number()?, self.number()?)
+                } else {
+                    Vec3::from(x, x, x)
+                };
+            }
+            "rotate_x" | "rotate_y" | "rotate_z" => {
+                let axis = match key {
+                    "rotate_x" => 0,
+                    "rotate_y" => 1,
+                    _ => 2,
+                };
+                let degrees = &pose.rotation_degrees;
+                let mut angles = [*degrees.x(), *degrees.y(), *degrees.z()];
+                angles[axis] = self.number()?;
+                pose.rotation_degrees = Vec3::from(angles[0], angles[1], angles[2]);
+            }
+            "translate" => pose.translation = self.vec3()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn parse_settings(&mut self, settings: &mut SceneSettings) -> Result<(), SceneError> {
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "width" => settings.width = Some(parser.positive_integer()?),
+                "height" => settings.height = Some(parser.positive_integer()?),
+                "samples" => settings.samples_per_pixel = Some(parser.positive_integer()?),
+                "max_depth" => settings.max_depth = Some(parser.positive_integer()?),
+                "seed" => settings.seed = Some(parser.integer()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })
+    }
+
+    fn parse_camera(&mut self, settings: &mut SceneSettings) -> Result<(), SceneError> {
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "look_from" => settings.look_from = Some(parser.vec3()?),
+                "look_at" => settings.look_at = Some(parser.vec3()?),
+                "up" => settings.up = Some(parser.vec3()?),
+                "vfov" => settings.vertical_fov_degrees = Some(parser.number()?),
+                "aperture" => settings.aperture = Some(parser.number()?),
+                "focus_distance" => settings.focus_distance = Some(parser.number()?),
+                "shutter_open" => settings.shutter_open = Some(parser.number()?),
+                "shutter_close" => settings.shutter_close = Some(parser.number()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })
+    }
+
+    fn parse_material(&mut self) -> Result<(), SceneError> {
+        let name_token = self.next();
+        let name = match &name_token.kind {
+            TokenKind::Word(name) | TokenKind::Str(name) => name.clone(),
+            other => {
+                return parse_error(
+                    name_token.line,
+                    name_token.column,
+                    format!("expected a material name, found {}", other.describe()),
+                )
+            }
+        };
+        if self.materials.contains_key(&name) {
+            return parse_error(
+                name_token.line,
+                name_token.column,
+                format!("material '{}' is already defined", name),
+            );
+        }
+
+        let kind_token = self.next();
+        let kind = match &kind_token.kind {
+            TokenKind::Word(kind) => kind.clone(),
+            other => {
+                return parse_error(
+                    kind_token.line,
+                    kind_token.column,
+                    format!("expected a material type, found {}", other.describe()),
+                )
+            }
+        };
+
+        let mut albedo = None;
+        let mut refraction_index = None;
+        let material = match kind.as_str() {
+            "lambertian" | "metal" => {
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "albedo" => albedo = Some(parser.vec3()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                let albedo = self.require(albedo, "albedo", &kind_token)?;
+                if kind == "lambertian" {
+                    MaterialDesc::Lambertian(albedo)
+                } else {
+                    MaterialDesc::Metal(albedo)
+                }
+            }
+            "dielectric" => {
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "refraction_index" => refraction_index = Some(parser.number()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                MaterialDesc::Dielectric(self.require(
+                    refraction_index,
+                    "refraction_index",
+                    &kind_token,
+                )?)
+            }
+            "light" => {
+                let mut emission = None;
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "emission" => emission = Some(parser.vec3()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                MaterialDesc::Light(self.require(emission, "emission", &kind_token)?)
+            }
+            _ => {
+                return parse_error(
+                    kind_token.line,
+                    kind_token.column,
+                    format!(
+                    "unknown material type '{}', expected lambertian, metal, dielectric or light",
+                    kind
+                ),
+                )
+            }
+        };
+
+        self.materials.insert(name, material);
+        Ok(())
+    }
+
+    fn parse_sphere(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut center = None;
+        let mut radius = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "center" => center = Some(parser.vec3()?),
+                "radius" => radius = Some(parser.number()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        let center = self.require(center, "center", start)?;
+        let radius = self.require(radius, "radius", start)?;
+        let material = self.require(material, "material", start)?;
+        Ok(Box::new(Sphere {
+            center,
+            radius,
+            material: material.build(),
+        }))
+    }
+
+    fn parse_moving_sphere(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut centers = [None; 2];
+        let mut times = [None; 2];
+        let mut radius = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "center0" => centers[0] = Some(parser.vec3()?),
+                "center1" => centers[1] = Some(parser.vec3()?),
+                "time0" => times[0] = Some(parser.number()?),
+                "time1" => times[1] = Some(parser.number()?),
+                "radius" => radius = Some(parser.number()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        let center0 = self.require(centers[0], "center0", start)?;
+        let center1 = self.require(centers[1], "center1", start)?;
+        let radius = self.require(radius, "radius", start)?;
+        let material = self.require(material, "material", start)?;
+        Ok(Box::new(MovingSphere {
+            center0,
+            center1,
+            time0: times[0].unwrap_or(0.0),
+            time1: times[1].unwrap_or(1.0),
+            radius,
+            material: material.build(),
+        }))
+    }
+
+    fn parse_rect(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut plane = None;
+        let mut min = None;
+        let mut max = None;
+        let mut offset = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "plane" => plane = Some(parser.plane()?),
+                "min" => min = Some((parser.number()?, parser.number()?)),
+                "max" => max = Some((parser.number()?, parser.number()?)),
+                "offset" => offset = Some(parser.number()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        Ok(Box::new(AxisAlignedRect::new(
+            self.require(plane, "plane", start)?,
+            self.require(min, "min", start)?,
+            self.require(max, "max", start)?,
+            self.require(offset, "offset", start)?,
+            self.require(material, "material", start)?.build(),
+        )))
+    }
+
+    fn parse_plane(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut point = None;
+        let mut normal = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "point" => point = Some(parser.vec3()?),
+                "normal" => normal = Some(parser.vec3()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        let point = self.require(point, "point", start)?;
+        let normal = self.require(normal, "normal", start)?;
+        if normal.square_length() == 0.0 {
+            return parse_error(
+                start.line,
+                start.column,
+                "a plane's normal must not be zero".to_string(),
+            );
+        }
+        Ok(Box::new(InfinitePlane::new(
+            point,
+            normal,
+            self.require(material, "material", start)?.build(),
+        )))
+    }
+
+    fn parse_quad(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut corner = None;
+        let mut u = None;
+        let mut v = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "corner" => corner = Some(parser.vec3()?),
+                "u" => u = Some(parser.vec3()?),
+                "v" => v = Some(parser.vec3()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        Ok(Box::new(Quad::new(
+            self.require(corner, "corner", start)?,
+            self.require(u, "u", start)?,
+            self.require(v, "v", start)?,
+            self.require(material, "material", start)?.build(),
+        )))
+    }
+
+    fn parse_box(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut min = None;
+        let mut max = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "min" => min = Some(parser.vec3()?),
+                "max" => max = Some(parser.vec3()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        Ok(Box::new(BoxShape::new(
+            self.require(min, "min", start)?,
+            self.require(max, "max", start)?,
+            self.require(material, "material", start)?.build(),
+        )))
+    }
+
+    fn parse_disk(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut center = None;
+        let mut normal = None;
+        let mut radius = None;
+        let mut inner_radius = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "center" => center = Some(parser.vec3()?),
+                "normal" => normal = Some(parser.vec3()?),
+                "radius" => radius = Some(parser.number()?),
+                "inner_radius" => inner_radius = Some(parser.number()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        Ok(Box::new(Disk::annulus(
+            self.require(center, "center", start)?,
+            self.require(normal, "normal", start)?,
+            inner_radius.unwrap_or(0.0),
+            self.require(radius, "radius", start)?,
+            self.require(material, "material", start)?.build(),
+        )))
+    }
+
+    // Cylinders run from `base` to `top`, cones from `base` to `apex` and
+    // paraboloids from `vertex` to `top`.
+    fn parse_quadric(&mut self, kind: &str, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let (from_name, to_name) = match kind {
+            "cylinder" => ("base", "top"),
+            "cone" => ("base", "apex"),
+            _ => ("vertex", "top"),
+        };
+        let mut from = None;
+        let mut to = None;
+        let mut radius = None;
+        let mut capped = false;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                name if name == from_name => from = Some(parser.vec3()?),
+                name if name == to_name => to = Some(parser.vec3()?),
+                "radius" => radius = Some(parser.number()?),
+                "capped" => capped = true,
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+        let from = self.require(from, from_name, start)?;
+        let to = self.require(to, to_name, start)?;
+        let radius = self.require(radius, "radius", start)?;
+        let material = self.require(material, "material", start)?.build();
+        if (to - from).square_length() == 0.0 {
+            return parse_error(
+                start.line,
+                start.column,
+                format!(
+                    "a {} needs distinct {} and {} points",
+                    kind, from_name, to_name
+                ),
+            );
+        }
+        Ok(match kind {
+            "cylinder" => {
+                let cylinder = Cylinder::new(from, to, radius, material);
+                Box::new(if capped { cylinder.capped() } else { cylinder })
+            }
+            "cone" => {
+                let cone = Cone::new(from, to, radius, material);
+                Box::new(if capped { cone.capped() } else { cone })
+            }
+            _ => {
+                let paraboloid = Paraboloid::new(from, to, radius, material);
+                Box::new(if capped {
+                    paraboloid.capped()
+                } else {
+                    paraboloid
+                })
+            }
+        })
+    }
+
+    fn parse_sdf(&mut self, start: &Token) -> Result<Box<dyn Hitable>, SceneError> {
+        let mut material = None;
+        let mut functions = Vec::new();
+        self.parse_block(|parser, key| {
+            if key == "material" {
+                material = Some(parser.material_reference()?);
+                return Ok(true);
+            }
+            parser.parse_distance_property(key, &mut functions)
+        })?;
+
+        let material = self.require(material, "material", start)?;
+        if functions.len() > 1 {
+            return parse_error(
+                start.line,
+                start.column,
+                "an sdf takes a single distance function, combine several with smooth_union"
+                    .to_string(),
+            );
+        }
+        let function = self.require(functions.pop(), "distance function", start)?;
+        Ok(Box::new(SdfShape::new(function, material.build())))
+    }
+
+    // Parses the distance function block named `key` into `functions`;
+    // false if `key` does not name one.
+    fn parse_distance_property(
+        &mut self,
+        key: &str,
+        functions: &mut Vec<Box<dyn DistanceFunction>>,
+    ) -> Result<bool, SceneError> {
+        if !DISTANCE_BLOCKS.contains(&key) {
+            return Ok(false);
+        }
+        let start = self.tokens[self.position - 1].clone();
+        let function: Box<dyn DistanceFunction> = match key {
+            "torus" => {
+                let mut center = None;
+                let mut major_radius = None;
+                let mut minor_radius = None;
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "center" => center = Some(parser.vec3()?),
+                        "major_radius" => major_radius = Some(parser.number()?),
+                        "minor_radius" => minor_radius = Some(parser.number()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                Box::new(sdf::Torus {
+                    center: center.unwrap_or_default(),
+                    major_radius: self.require(major_radius, "major_radius", &start)?,
+                    minor_radius: self.require(minor_radius, "minor_radius", &start)?,
+                })
+            }
+            "rounded_box" => {
+                let mut center = None;
+                let mut half_extents = None;
+                let mut radius = None;
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "center" => center = Some(parser.vec3()?),
+                        "half_extents" => half_extents = Some(parser.vec3()?),
+                        "radius" => radius = Some(parser.number()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                Box::new(sdf::RoundedBox {
+                    center: center.unwrap_or_default(),
+                    half_extents: self.require(half_extents, "half_extents", &start)?,
+                    radius: radius.unwrap_or(0.0),
+                })
+            }
+            "capsule" => {
+                let mut a = None;
+                let mut b = None;
+                let mut radius = None;
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "a" => a = Some(parser.vec3()?),
+                        "b" => b = Some(parser.vec3()?),
+                        "radius" => radius = Some(parser.number()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                Box::new(sdf::Capsule {
+                    a: self.require(a, "a", &start)?,
+                    b: self.require(b, "b", &start)?,
+                    radius: self.require(radius, "radius", &start)?,
+                })
+            }
+            "mandelbulb" => {
+                let mut center = None;
+                let mut power = None;
+                let mut iterations = None;
+                self.parse_block(|parser, key| {
+                    match key.as_str() {
+                        "center" => center = Some(parser.vec3()?),
+                        "power" => power = Some(parser.number()?),
+                        "iterations" => iterations = Some(parser.positive_integer()?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                Box::new(sdf::Mandelbulb {
+                    center: center.unwrap_or_default(),
+                    power: power.unwrap_or(8.0),
+                    iterations: iterations.unwrap_or(12),
+                })
+            }
+            _ => {
+                // The operators fold any number of operands from the left.
+                let mut smoothness = None;
+                let mut operands = Vec::new();
+                self.parse_block_repeating(&DISTANCE_BLOCKS, |parser, key| {
+                    if key == "smoothness" {
+                        smoothness = Some(parser.number()?);
+                        return Ok(true);
+                    }
+                    parser.parse_distance_property(key, &mut operands)
+                })?;
+                if operands.len() < 2 {
+                    return parse_error(
+                        start.line,
+                        start.column,
+                        format!("{} needs at least two shapes", key),
+                    );
+                }
+                let smoothness = smoothness.unwrap_or(0.0);
+                let mut operands = operands.into_iter();
+                let first = operands.next().unwrap();
+                operands.fold(first, |a, b| -> Box<dyn DistanceFunction> {
+                    if key == "smooth_union" {
+                        Box::new(sdf::SmoothUnion { a, b, smoothness })
+                    } else {
+                        Box::new(sdf::SmoothSubtraction { a, b, smoothness })
+                    }
+                })
+            }
+        };
+        functions.push(function);
+        Ok(true)
+    }
+
+    fn parse_obj(&mut self, start: &Token) -> Result<Vec<Box<dyn Hitable>>, SceneError> {
+        let (file, material) = self.parse_model_block(start)?;
+        let path = self.directory.join(&file);
+        let model = match obj::load_obj(&path) {
+            Ok(model) => model,
+            Err(err) => return parse_error(start.line, start.column, err.to_string()),
+        };
+        self.files.push(path);
+        self.files.extend(model.material_libraries.iter().cloned());
+        self.warnings
+            .extend(model.warnings.iter().map(ToString::to_string));
+        let hitables = match material {
+            Some(material) => model.into_hitables(Some(&|| material.build())),
+            None => model.into_hitables(None),
+        };
+        if hitables.is_empty() {
+            return parse_error(
+                start.line,
+                start.column,
+                format!("{} does not contain any faces", file),
+            );
+        }
+        Ok(hitables)
+    }
+
+    fn parse_ply(&mut self, start: &Token) -> Result<Vec<Box<dyn Hitable>>, SceneError> {
+        let (file, material) = self.parse_model_block(start)?;
+        let path = self.directory.join(&file);
+        let mesh = match ply::load_ply(&path) {
+            Ok(mesh) => mesh,
+            Err(err) => return parse_error(start.line, start.column, err.to_string()),
+        };
+        self.files.push(path);
+        if mesh.indices.is_empty() {
+            return parse_error(
+                start.line,
+                start.column,
+                format!("{} does not contain any faces", file),
+            );
+        }
+        let material = match material {
+            Some(material) => material.build(),
+            None => mesh.default_material(),
+        };
+        Ok(mesh.into_mesh(material).into_triangles())
+    }
+
+    // "{ file "path" material name }" with an optional material.
+    fn parse_model_block(
+        &mut self,
+        start: &Token,
+    ) -> Result<(String, Option<MaterialDesc>), SceneError> {
+        let mut file = None;
+        let mut material = None;
+        self.parse_block(|parser, key| {
+            match key.as_str() {
+                "file" => file = Some(parser.string()?),
+                "material" => material = Some(parser.material_reference()?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok((self.require(file, "file", start)?, material))
+    }
+
+    // Parses "{ key value... }", handing each key to `property` which returns
+    // false for keys it does not know.
+    fn parse_block<F>(&mut self, property: F) -> Result<(), SceneError>
+    where
+        F: FnMut(&mut Self, &String) -> Result<bool, SceneError>,
+    {
+        self.parse_block_repeating(&[], property)
+    }
+
+    // Like `parse_block`, but the keys in `repeatable` may appear any number
+    // of times.
+    fn parse_block_repeating<F>(
+        &mut self,
+        repeatable: &[&str],
+        mut property: F,
+    ) -> Result<(), SceneError>
+    where
+        F: FnMut(&mut Self, &String) -> Result<bool, SceneError>,
+    {
+        self.expect(TokenKind::OpenBrace)?;
+        let mut seen: Vec<String> = Vec::new();
+        loop {
+            let token = self.next();
+            let key = match &token.kind {
+                TokenKind::CloseBrace => return Ok(()),
+                TokenKind::Word(key) => key.clone(),
+                other => {
+                    return parse_error(
+                        token.line,
+                        token.column,
+                        format!(
+                            "expected a property name or '}}', found {}",
+                            other.describe()
+                        ),
+                    )
+                }
+            };
+
+            if seen.contains(&key) && !repeatable.contains(&key.as_str()) {
+                return parse_error(
+                    token.line,
+                    token.column,
+                    format!("property '{}' is set more than once", key),
+                );
+            }
+            if !property(self, &key)? {
+                return parse_error(
+                    token.line,
+                    token.column,
+                    format!("unknown property '{}'", key),
+                );
+            }
+            seen.push(key);
+        }
+    }
+
+    fn require<T>(&self, value: Option<T>, name: &str, owner: &Token) -> Result<T, SceneError> {
+        match value {
+            Some(value) => Ok(value),
+            None => parse_error(
+                owner.line,
+                owner.column,
+                format!("missing required property '{}'", name),
+            ),
+        }
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), SceneError> {
+        let token = self.next();
+        if token.kind == kind {
+            Ok(())
+        } else {
+            parse_error(
+                token.line,
+                token.column,
+                format!(
+                    "expected {}, found {}",
+                    kind.describe(),
+                    token.kind.describe()
+                ),
+            )
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, SceneError> {
+        let token = self.next();
+        match &token.kind {
+            // The tokeniser has already checked that the text is a number.
+            TokenKind::Number(text) => Ok(text.parse().unwrap_or(0.0)),
+            other => parse_error(
+                token.line,
+                token.column,
+                format!("expected a number, found {}", other.describe()),
+            ),
+        }
+    }
+
+    fn integer(&mut self) -> Result<u64, SceneError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Number(text) => match text.parse() {
+                Ok(value) => Ok(value),
+                Err(_) => parse_error(
+                    token.line,
+                    token.column,
+                    format!("expected a whole number, found {}", text),
+                ),
+            },
+            other => parse_error(
+                token.line,
+                token.column,
+                format!("expected a whole number, found {}", other.describe()),
+            ),
+        }
+    }
+
+    fn positive_integer<T: TryFrom<u64>>(&mut self) -> Result<T, SceneError> {
+        let token = self.tokens[self.position].clone();
+        let value = self.integer()?;
+        if value == 0 {
+            return parse_error(
+                token.line,
+                token.column,
+                "expected a value of at least 1".to_string(),
+            );
+        }
+        match T::try_from(value) {
+            Ok(converted) => Ok(converted),
+            Err(_) => parse_error(
+                token.line,
+                token.column,
+                format!("value {} is too large", value),
+            ),
+        }
+    }
+
+    fn next_is_number(&self) -> bool {
+        matches!(self.tokens[self.position].kind, TokenKind::Number(_))
+    }
+
+    fn object_reference(&mut self) -> Result<Arc<dyn Hitable>, SceneError> {
+        let token = self.next();
+        let name = match &token.kind {
+            TokenKind::Word(name) | TokenKind::Str(name) => name,
+            other => {
+                return parse_error(
+                    token.line,
+                    token.column,
+                    format!("expected an object name, found {}", other.describe()),
+                )
+            }
+        };
+        match self.objects.get(name) {
+            Some(object) => Ok(object.clone()),
+            None => parse_error(
+                token.line,
+                token.column,
+                format!("unknown object '{}'", name),
+            ),
+        }
+    }
+
+    fn plane(&mut self) -> Result<Plane, SceneError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Word(word) if word == "xy" => Ok(Plane::Xy),
+            TokenKind::Word(word) if word == "xz" => Ok(Plane::Xz),
+            TokenKind::Word(word) if word == "yz" => Ok(Plane::Yz),
+            other => parse_error(
+                token.line,
+                token.column,
+                format!(
+                    "expected a plane (xy, xz or yz), found {}",
+                    other.describe()
+                ),
+            ),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SceneError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Str(value) => Ok(value.clone()),
+            other => parse_error(
+                token.line,
+                token.column,
+                format!("expected a quoted string, found {}", other.describe()),
+            ),
+        }
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, SceneError> {
+        Ok(Vec3::from(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn material_reference(&mut self) -> Result<MaterialDesc, SceneError> {
+        let token = self.next();
+        let name = match &token.kind {
+            TokenKind::Word(name) | TokenKind::Str(name) => name,
+            other => {
+                return parse_error(
+                    token.line,
+                    token.column,
+                    format!("expected a material name, found {}", other.describe()),
+                )
+            }
+        };
+        match self.materials.get(name) {
+            Some(material) => Ok(*material),
+            None => parse_error(
+                token.line,
+                token.column,
+                format!("unknown material '{}'", name),
+            ),
+        }
+    }
+}
+
+// The final scene from "Ray Tracing in One Weekend".
+pub fn random_scene(rnd: &mut Random) -> Vec<Box<dyn Hitable>> {
+    let n = 500;
+    let mut list: Vec<Box<dyn Hitable>> = Vec::with_capacity(n + 1);
+    list.push(Box::new(InfinitePlane::new(
+        Vec3::from(0.0, 0.0, 0.0),
+        Vec3::from(0.0, 1.0, 0.0),
+        Box::new(Lambertian::with_albedo(Vec3::from(0.5, 0.5, 0.5))),
+    )));
+    for a in -11..11i16 {
+        for b in -11..11i16 {
+            let choose_mat = rnd.gen();
+            let center = Vec3::from(
+                f32::from(a) + 0.9 * rnd.gen(),
+                0.2,
+                f32::from(b) + 0.9 * rnd.gen(),
+            );
+            if (center - Vec3::from(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material: Box<dyn Material> = match choose_mat {
+                    x if x < 0.8 => Box::new(Lambertian::with_albedo(Vec3::from(
+                        rnd.gen() * rnd.gen(),
+                        rnd.gen() * rnd.gen(),
+                        rnd.gen() * rnd.gen(),
+                    ))),
+                    x if x < 0.95 => Box::new(Metal::with_albedo(Vec3::from(
+                        0.5 * (1.0 + rnd.gen()),
+                        0.5 * (1.0 + rnd.gen()),
+                        0.5 * (1.0 + rnd.gen()),
+                    ))),
+                    _ => Box::new(Dielectric::with_refraction_index(1.5)),
+                };
+                list.push(build_sphere(center, 0.2, material));
+            }
+        }
+    }
+
+    list.push(build_sphere(
+        Vec3::from(0.0, 1.0, 0.0),
+        1.0,
+        Box::new(Dielectric::with_refraction_index(1.5)),
+    ));
+    list.push(build_sphere(
+        Vec3::from(-4.0, 1.0, 0.0),
+        1.0,
+        Box::new(Lambertian::with_albedo(Vec3::from(0.4, 0.2, 0.1))),
+    ));
+    list.push(build_sphere(
+        Vec3::from(4.0, 1.0, 0.0),
+        1.0,
+        Box::new(Metal::with_albedo(Vec3::from(0.7, 0.6, 0.5))),
+    ));
+
+    list
+}
+
+fn build_sphere(center: Vec3, radius: f32, material: Box<dyn Material>) -> Box<dyn Hitable> {
+    let sphere = Sphere {
+        center,
+        radius,
+        material,
+    };
+    Box::new(sphere)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(source: &str) -> (usize, usize, String) {
+        match parse_scene(source) {
+            Err(SceneError::Parse {
+                line,
+                column,
+                message,
+            }) => (line, column, message),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("parsed without an error"),
+        }
+    }
+
+    #[test]
+    fn parses_a_representative_scene() {
+        let scene = parse_scene(
+            r#"
+            # Every kind of block, as in the format description.
+            settings { width 320 height 200 samples 8 max_depth 5 seed 7 }
+            camera {
+                look_from 13 2 3
+                look_at 0 0 0
+                vfov 20
+                shutter_open 0
+                shutter_close 1
+            }
+            material ground lambertian { albedo 0.5 0.5 0.5 }
+            material steel metal { albedo 0.7 0.6 0.5 }
+            material glass dielectric { refraction_index 1.5 }
+            material lamp light { emission 4 4 4 }
+            sphere { center 0 -1000 0 radius 1000 material ground }
+            sphere { center 0 1 0 radius 1 material glass }
+            sphere { center 4 1 0 radius 1 material steel }
+            moving_sphere { center0 0 1 0 center1 0 1.5 0 radius 0.5 material steel }
+            rect { plane xz min 213 227 max 343 332 offset 554 material lamp }
+            plane { point 0 0 0 normal 0 1 0 material ground }
+            quad { corner 0 0 0 u 1 0 0 v 0 1 0 material steel }
+            box { min 0 0 0 max 1 2 1 material ground }
+            disk { center 0 0 0 normal 0 1 0 radius 2 inner_radius 1 material steel }
+            cylinder { base 0 0 0 top 0 2 0 radius 0.5 capped material steel }
+            sdf {
+                material steel
+                smooth_union {
+                    smoothness 0.2
+                    torus { center 0 1 0 major_radius 1 minor_radius 0.25 }
+                    capsule { a 0 0 0 b 0 2 0 radius 0.3 }
+                }
+            }
+            difference {
+                box { min -1 -1 -1 max 1 1 1 material steel }
+                cylinder { base 0 -2 0 top 0 2 0 radius 0.5 capped material steel }
+            }
+            object crate { box { min 0 0 0 max 1 1 1 material ground } }
+            instance { object crate scale 2 rotate_y 15 translate 3 0 -1 }
+            instance {
+                object crate
+                keyframe 0 { translate -1 0 0 }
+                keyframe 1 { translate 1 0 0 rotate_y 90 }
+            }
+            medium { object crate density 0.05 albedo 1 1 1 }
+            "#,
+        )
+        .unwrap();
+
+        // Objects are only added through their instances and media.
+        assert_eq!(scene.hitables.len(), 15);
+        let settings = &scene.settings;
+        assert_eq!((settings.width, settings.height), (Some(320), Some(200)));
+        assert_eq!(settings.samples_per_pixel, Some(8));
+        assert_eq!(settings.max_depth, Some(5));
+        assert_eq!(settings.seed, Some(7));
+        assert_eq!(settings.vertical_fov_degrees, Some(20.0));
+        assert_eq!(settings.shutter_close, Some(1.0));
+        assert!(settings.aperture.is_none());
+        let look_from = settings.look_from.unwrap();
+        assert_eq!(
+            (look_from.x(), look_from.y(), look_from.z()),
+            (&13.0, &2.0, &3.0)
+        );
+        assert!(scene.files.is_empty() && scene.warnings.is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_properties_where_they_are() {
+        let (line, column, message) = error_at("camera {\n  from 1 2 3\n}");
+        assert_eq!((line, column), (2, 3));
+        assert_eq!(message, "unknown property 'from'");
+    }
+
+    #[test]
+    fn reports_unknown_materials_where_they_are_used() {
+        let (line, column, message) =
+            error_at("material red lambertian { albedo 1 0 0 }\nsphere { radius 1 material blue }");
+        assert_eq!((line, column), (2, 28));
+        assert_eq!(message, "unknown material 'blue'");
+    }
+
+    #[test]
+    fn reports_properties_set_twice_at_the_second() {
+        let (line, column, message) = error_at("settings {\n  width 10\n  width 20\n}");
+        assert_eq!((line, column), (3, 3));
+        assert_eq!(message, "property 'width' is set more than once");
+    }
+
+    #[test]
+    fn reports_short_vectors_at_the_token_after_them() {
+        let (line, column, message) = error_at("camera { look_at 1 2 }");
+        assert_eq!((line, column), (1, 22));
+        assert_eq!(message, "expected a number, found '}'");
+    }
+
+    #[test]
+    fn reports_values_out_of_range() {
+        let (line, column, message) = error_at("settings { samples 0 }");
+        assert_eq!((line, column), (1, 20));
+        assert_eq!(message, "expected a value of at least 1");
+
+        let (line, column, message) = error_at("settings {\n  samples 5000000000\n}");
+        assert_eq!((line, column), (2, 11));
+        assert_eq!(message, "value 5000000000 is too large");
+
+        // Checks made once the block is read point at the block.
+        let (line, column, message) = error_at(
+            "material white lambertian { albedo 1 1 1 }\n\
+             object fog { box { min 0 0 0 max 1 1 1 material white } }\n\
+             medium { object fog density -1 }",
+        );
+        assert_eq!((line, column), (3, 1));
+        assert_eq!(message, "the medium density must be positive, got -1");
+    }
+}
@@ -0,0 +1,279 @@
+// Shapes given by signed distance functions, intersected by sphere tracing:
+// stepping along the ray by the distance to the nearest surface, which can
+// never overshoot it.
+
+use crate::aabb::Aabb;
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::ray::*;
+use crate::vec3::*;
+
+// Distance from `point` to the surface, negative inside. It may
+// underestimate, e.g. for smooth blends and fractals, which only costs
+// extra steps, but must never overestimate.
+pub trait DistanceFunction: Send + Sync {
+    fn distance(&self, point: &Vec3) -> f32;
+    // A box the surface stays within.
+    fn bounds(&self) -> Aabb;
+}
+
+// A ring around the y axis: `major_radius` from the center to the middle of
+// the tube, whose own radius is `minor_radius`.
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl DistanceFunction for Torus {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let p = point - &self.center;
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::from(outer, self.minor_radius, outer);
+        Aabb::build(self.center - extent, self.center + extent)
+    }
+}
+
+// An axis-aligned box with its edges rounded off by `radius`; the half
+// extents include the rounding.
+pub struct RoundedBox {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    pub radius: f32,
+}
+
+impl DistanceFunction for RoundedBox {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let p = point - &self.center;
+        let rounding = Vec3::from(self.radius, self.radius, self.radius);
+        let q = Vec3::from(p.x().abs(), p.y().abs(), p.z().abs()) - self.half_extents + rounding;
+        let outside = q.max(&Vec3::default()).length();
+        let inside = q.max_elem().min(0.0);
+        outside + inside - self.radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::build(
+            self.center - self.half_extents,
+            self.center + self.half_extents,
+        )
+    }
+}
+
+// All points within `radius` of the segment from `a` to `b`; a sphere when
+// the two are the same.
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f32,
+}
+
+impl DistanceFunction for Capsule {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let pa = point - &self.a;
+        let ba = self.b - self.a;
+        let length = ba.square_length();
+        let h = if length > 0.0 {
+            (dot(&pa, &ba) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (pa - &ba * h).length() - self.radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        let extent = Vec3::from(self.radius, self.radius, self.radius);
+        Aabb::build(self.a.min(&self.b) - extent, self.a.max(&self.b) + extent)
+    }
+}
+
+// The union of two shapes with the seam filleted over about `smoothness`.
+pub struct SmoothUnion {
+    pub a: Box<dyn DistanceFunction>,
+    pub b: Box<dyn DistanceFunction>,
+    pub smoothness: f32,
+}
+
+impl DistanceFunction for SmoothUnion {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let (a, b) = (self.a.distance(point), self.b.distance(point));
+        let k = self.smoothness;
+        if k <= 0.0 {
+            return a.min(b);
+        }
+        let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+        b + (a - b) * h - k * h * (1.0 - h)
+    }
+
+    // The fillet swells the union by at most a quarter of the smoothness.
+    fn bounds(&self) -> Aabb {
+        let grow = self.smoothness.max(0.0) * 0.25;
+        let extent = Vec3::from(grow, grow, grow);
+        let union = Aabb::surrounding_box(&self.a.bounds(), &self.b.bounds());
+        Aabb::build(union.min - extent, union.max + extent)
+    }
+}
+
+// `a` with `b` carved out of it, the edge rounded over about `smoothness`.
+pub struct SmoothSubtraction {
+    pub a: Box<dyn DistanceFunction>,
+    pub b: Box<dyn DistanceFunction>,
+    pub smoothness: f32,
+}
+
+impl DistanceFunction for SmoothSubtraction {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let (a, b) = (self.a.distance(point), -self.b.distance(point));
+        let k = self.smoothness;
+        if k <= 0.0 {
+            return a.max(b);
+        }
+        let h = (0.5 - 0.5 * (b - a) / k).clamp(0.0, 1.0);
+        b + (a - b) * h + k * h * (1.0 - h)
+    }
+
+    // Carving only removes material.
+    fn bounds(&self) -> Aabb {
+        self.a.bounds()
+    }
+}
+
+// The power-n Mandelbulb fractal, through its distance estimate. Higher
+// iteration counts resolve finer detail at a proportional cost.
+pub struct Mandelbulb {
+    pub center: Vec3,
+    pub power: f32,
+    pub iterations: u32,
+}
+
+impl DistanceFunction for Mandelbulb {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let c = point - &self.center;
+        let mut w = c;
+        let mut m = w.square_length();
+        let mut dz = 1.0;
+        for _ in 0..self.iterations {
+            let r = m.sqrt();
+            dz = self.power * r.powf(self.power - 1.0) * dz + 1.0;
+            let theta = (w.y() / r).clamp(-1.0, 1.0).acos() * self.power;
+            let phi = w.x().atan2(*w.z()) * self.power;
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            w = c + &Vec3::from(sin_theta * sin_phi, cos_theta, sin_theta * cos_phi)
+                * r.powf(self.power);
+            m = w.square_length();
+            if m > 256.0 {
+                break;
+            }
+        }
+        0.25 * m.ln() * m.sqrt() / dz
+    }
+
+    // The bulb stays within a radius of about 1.2 for the usual powers.
+    fn bounds(&self) -> Aabb {
+        let extent = Vec3::from(1.5, 1.5, 1.5);
+        Aabb::build(self.center - extent, self.center + extent)
+    }
+}
+
+// Sphere tracing gives up after this many steps and reports a miss.
+const MAX_STEPS: usize = 512;
+// Distance at which the ray counts as touching the surface.
+const SURFACE_DISTANCE: f32 = 1.0e-4;
+// Offset for the finite differences that estimate the normal.
+const NORMAL_OFFSET: f32 = 1.0e-4;
+
+pub struct SdfShape {
+    function: Box<dyn DistanceFunction>,
+    bounding_box: Aabb,
+    material: Box<dyn Material>,
+}
+
+impl SdfShape {
+    pub fn new(function: Box<dyn DistanceFunction>, material: Box<dyn Material>) -> Self {
+        SdfShape {
+            bounding_box: function.bounds(),
+            function,
+            material,
+        }
+    }
+
+    // The gradient of the distance, from four samples at the corners of a
+    // tetrahedron around `point`.
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let h = NORMAL_OFFSET;
+        let corners = [
+            Vec3::from(1.0, -1.0, -1.0),
+            Vec3::from(-1.0, -1.0, 1.0),
+            Vec3::from(-1.0, 1.0, -1.0),
+            Vec3::from(1.0, 1.0, 1.0),
+        ];
+        corners
+            .iter()
+            .fold(Vec3::default(), |gradient, corner| {
+                gradient + corner * self.function.distance(&(point + &(corner * h)))
+            })
+            .make_normalised()
+    }
+}
+
+// Where the ray enters and leaves `bounds`, if it does within the range.
+fn box_interval(bounds: &Aabb, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+    let inv_d = ray.direction.invert_elems();
+    let t0 = (bounds.min - ray.origin).direct_product(&inv_d);
+    let t1 = (bounds.max - ray.origin).direct_product(&inv_d);
+    let enter = t_min.max(t0.min(&t1).max_elem());
+    let leave = t_max.min(t0.max(&t1).min_elem());
+    if enter < leave {
+        Some((enter, leave))
+    } else {
+        None
+    }
+}
+
+impl Hitable for SdfShape {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (mut t, leave) = box_interval(&self.bounding_box, ray, t_min, t_max)?;
+        let length = ray.direction.length();
+
+        // A ray starting on the surface, e.g. one just scattered off it,
+        // first has to get clear of it before it can hit it again. That does
+        // not apply where the march starts on the box instead, which the
+        // surface may touch. Inside the shape the distance is negative, so
+        // steps use its magnitude.
+        let mut leaving = t == t_min
+            && self.function.distance(&ray.point_at_parameter(t)).abs() < SURFACE_DISTANCE;
+        for _ in 0..MAX_STEPS {
+            let p = ray.point_at_parameter(t);
+            let distance = self.function.distance(&p).abs();
+            if leaving {
+                leaving = distance < SURFACE_DISTANCE;
+            } else if distance < SURFACE_DISTANCE {
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal: self.normal(&p),
+                    // Distance fields have no natural parameterisation.
+                    u: 0.0,
+                    v: 0.0,
+                    colour: Vec3::from(1.0, 1.0, 1.0),
+                    material: &*self.material,
+                });
+            }
+            t += distance.max(SURFACE_DISTANCE) / length;
+            if t >= leave {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+}
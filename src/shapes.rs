@@ -0,0 +1,340 @@
+// Planar primitives from "Ray Tracing: The Next Week": axis-aligned
+// rectangles, parallelograms and boxes made of six rectangles, plus
+// infinite planes.
+//
+// Rectangles, parallelograms and planes are single-sided sheets with no
+// inside, so like open meshes and quadrics their normal faces the ray. Only
+// box faces keep a fixed, outward normal.
+
+use crate::aabb::{Aabb, MIN_BOX_EXTENT};
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::ray::*;
+use crate::vec3::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl Plane {
+    // The two in-plane axes and the axis along the normal.
+    fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Plane::Xy => (0, 1, 2),
+            Plane::Xz => (0, 2, 1),
+            Plane::Yz => (1, 2, 0),
+        }
+    }
+}
+
+// The geometry of a rectangle, shared by `AxisAlignedRect` and the faces of
+// `BoxShape`. The normal points along the positive axis unless flipped,
+// which only box faces are.
+#[derive(Copy, Clone)]
+struct Rect {
+    plane: Plane,
+    min: (f32, f32),
+    max: (f32, f32),
+    offset: f32,
+    flipped: bool,
+}
+
+impl Rect {
+    // Distance, texture coordinates and normal of the hit.
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32, Vec3)> {
+        let (a, b, k) = self.plane.axes();
+        let t = (self.offset - ray.origin.get(k)) / ray.direction.get(k);
+        // Also rejects the NaN of a ray running inside the plane.
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let hit_a = ray.origin.get(a) + t * ray.direction.get(a);
+        let hit_b = ray.origin.get(b) + t * ray.direction.get(b);
+        if hit_a < self.min.0 || hit_a > self.max.0 || hit_b < self.min.1 || hit_b > self.max.1 {
+            return None;
+        }
+
+        let mut normal = [0.0; 3];
+        normal[k] = if self.flipped { -1.0 } else { 1.0 };
+        Some((
+            t,
+            (hit_a - self.min.0) / (self.max.0 - self.min.0),
+            (hit_b - self.min.1) / (self.max.1 - self.min.1),
+            Vec3::from(normal[0], normal[1], normal[2]),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (a, b, k) = self.plane.axes();
+        let mut min = [0.0; 3];
+        let mut max = [0.0; 3];
+        min[a] = self.min.0;
+        max[a] = self.max.0;
+        min[b] = self.min.1;
+        max[b] = self.max.1;
+        min[k] = self.offset;
+        max[k] = self.offset;
+        Aabb::build(
+            Vec3::from(min[0], min[1], min[2]),
+            Vec3::from(max[0], max[1], max[2]),
+        )
+        .padded(MIN_BOX_EXTENT)
+    }
+}
+
+pub struct AxisAlignedRect {
+    rect: Rect,
+    material: Box<dyn Material>,
+}
+
+impl AxisAlignedRect {
+    // `min` and `max` are the corners within the plane, e.g. (x, z) for
+    // `Plane::Xz`, which sits at `offset` along the remaining axis.
+    pub fn new(
+        plane: Plane,
+        min: (f32, f32),
+        max: (f32, f32),
+        offset: f32,
+        material: Box<dyn Material>,
+    ) -> Self {
+        AxisAlignedRect {
+            rect: Rect {
+                plane,
+                min,
+                max,
+                offset,
+                flipped: false,
+            },
+            material,
+        }
+    }
+}
+
+impl Hitable for AxisAlignedRect {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t, u, v, normal) = self.rect.hit(ray, t_min, t_max)?;
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: facing(normal, ray),
+            u,
+            v,
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material: &*self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.rect.bounding_box()
+    }
+}
+
+// The parallelogram spanned by `u` and `v` from `corner`.
+pub struct Quad {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // The plane is dot(normal, p) = distance; `w` maps points in it to
+    // coordinates along u and v.
+    distance: f32,
+    w: Vec3,
+    material: Box<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: Box<dyn Material>) -> Self {
+        let n = cross(&u, &v);
+        let normal = n.make_normalised();
+        Quad {
+            corner,
+            u,
+            v,
+            normal,
+            distance: dot(&normal, &corner),
+            w: &n / n.square_length(),
+            material,
+        }
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let denominator = dot(&self.normal, &ray.direction);
+        if denominator.abs() < 1.0e-8 {
+            return None;
+        }
+        let t = (self.distance - dot(&self.normal, &ray.origin)) / denominator;
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+
+        let p = ray.point_at_parameter(t);
+        let planar = p - self.corner;
+        let alpha = dot(&self.w, &cross(&planar, &self.v));
+        let beta = dot(&self.w, &cross(&self.u, &planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord {
+            t,
+            p,
+            normal: facing(self.normal, ray),
+            u: alpha,
+            v: beta,
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material: &*self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let corners = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        let min = corners
+            .iter()
+            .fold(corners[0], |min, corner| min.min(corner));
+        let max = corners
+            .iter()
+            .fold(corners[0], |max, corner| max.max(corner));
+        Aabb::build(min, max).padded(MIN_BOX_EXTENT)
+    }
+}
+
+// An axis-aligned box made of six rectangles with outward normals, all
+// sharing one material.
+pub struct BoxShape {
+    min: Vec3,
+    max: Vec3,
+    faces: [Rect; 6],
+    material: Box<dyn Material>,
+}
+
+impl BoxShape {
+    pub fn new(min: Vec3, max: Vec3, material: Box<dyn Material>) -> Self {
+        let (low, high) = (min.min(&max), min.max(&max));
+        let face = |plane: Plane, offset: f32, flipped: bool| {
+            let (a, b, _) = plane.axes();
+            Rect {
+                plane,
+                min: (*low.get(a), *low.get(b)),
+                max: (*high.get(a), *high.get(b)),
+                offset,
+                flipped,
+            }
+        };
+        BoxShape {
+            min: low,
+            max: high,
+            faces: [
+                face(Plane::Xy, *high.z(), false),
+                face(Plane::Xy, *low.z(), true),
+                face(Plane::Xz, *high.y(), false),
+                face(Plane::Xz, *low.y(), true),
+                face(Plane::Yz, *high.x(), false),
+                face(Plane::Yz, *low.x(), true),
+            ],
+            material,
+        }
+    }
+}
+
+impl Hitable for BoxShape {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut t_max = t_max;
+        for face in &self.faces {
+            if let Some(hit) = face.hit(ray, t_min, t_max) {
+                t_max = hit.0;
+                closest = Some(hit);
+            }
+        }
+
+        let (t, u, v, normal) = closest?;
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal,
+            u,
+            v,
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material: &*self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::build(self.min, self.max).padded(MIN_BOX_EXTENT)
+    }
+}
+
+// The plane through `point` at right angles to `normal`, without edges. Its box
+// is infinite, so `World` keeps it out of the BVH. Texture coordinates are
+// distances along two directions in the plane, repeating textures across it.
+pub struct InfinitePlane {
+    point: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Box<dyn Material>,
+}
+
+impl InfinitePlane {
+    pub fn new(point: Vec3, normal: Vec3, material: Box<dyn Material>) -> Self {
+        let normal = &normal / normal.length();
+        let helper = if normal.x().abs() > 0.9 {
+            Vec3::from(0.0, 1.0, 0.0)
+        } else {
+            Vec3::from(1.0, 0.0, 0.0)
+        };
+        let tangent = cross(&helper, &normal);
+        let tangent = &tangent / tangent.length();
+        InfinitePlane {
+            point,
+            normal,
+            tangent,
+            bitangent: cross(&normal, &tangent),
+            material,
+        }
+    }
+}
+
+impl Hitable for InfinitePlane {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let t = dot(&self.normal, &(self.point - ray.origin)) / dot(&self.normal, &ray.direction);
+        // Also rejects the NaN and infinities of a ray parallel to the plane.
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let p = ray.point_at_parameter(t);
+        let planar = p - self.point;
+        Some(HitRecord {
+            t,
+            p,
+            normal: facing(self.normal, ray),
+            u: dot(&planar, &self.tangent),
+            v: dot(&planar, &self.bitangent),
+            colour: Vec3::from(1.0, 1.0, 1.0),
+            material: &*self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::unbounded()
+    }
+}
+
+// Turns the normal of a single-sided surface toward where the ray came from.
+fn facing(normal: Vec3, ray: &Ray) -> Vec3 {
+    if dot(&normal, &ray.direction) > 0.0 {
+        &normal * -1.0
+    } else {
+        normal
+    }
+}
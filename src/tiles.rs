@@ -0,0 +1,107 @@
+// Splits the image into square tiles and orders them for the workers.
+// Rows are counted from the top of the image, matching the framebuffer.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+}
+
+pub fn build_tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    let mut grid: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => grid = spiral_order(columns, rows),
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    grid.into_iter()
+        .map(|(column, row)| Tile {
+            x0: column * tile_size,
+            y0: row * tile_size,
+            x1: width.min((column + 1) * tile_size),
+            y1: height.min((row + 1) * tile_size),
+        })
+        .collect()
+}
+
+// Walks outwards from the centre tile, so the middle of the frame (usually
+// the subject) finishes first.
+fn spiral_order(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let total = columns * rows;
+    let mut order = Vec::with_capacity(total);
+    let mut x = ((columns - 1) / 2) as isize;
+    let mut y = ((rows - 1) / 2) as isize;
+    let (mut dx, mut dy) = (1isize, 0isize);
+    let mut leg_length = 1;
+
+    let visit = |x: isize, y: isize, order: &mut Vec<(usize, usize)>| {
+        if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+            order.push((x as usize, y as usize));
+        }
+    };
+
+    visit(x, y, &mut order);
+    while order.len() < total {
+        // Each leg length is used twice: right, down, left 2, up 2, ...
+        for _ in 0..2 {
+            for _ in 0..leg_length {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut order);
+            }
+            let turned = (-dy, dx);
+            dx = turned.0;
+            dy = turned.1;
+        }
+        leg_length += 1;
+    }
+
+    order
+}
+
+// Distance along a Hilbert curve covering a side x side grid.
+fn hilbert_index(side: usize, column: usize, row: usize) -> usize {
+    let (mut x, mut y) = (column, row);
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve stays continuous.
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
@@ -0,0 +1,479 @@
+// Affine transforms and instancing: one piece of geometry, shared through an
+// `Arc`, can be placed any number of times with its own transform.
+
+use crate::aabb::Aabb;
+use crate::hitable::Hitable;
+use crate::ray::*;
+use crate::vec3::*;
+
+use std::ops;
+use std::sync::Arc;
+
+// Row-major 4x4 matrix acting on column vectors, so `a * b` applies `b`
+// first.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Matrix4 {
+    pub fn identity() -> Self {
+        Self::scaling(Vec3::from(1.0, 1.0, 1.0))
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, *offset.x()],
+                [0.0, 1.0, 0.0, *offset.y()],
+                [0.0, 0.0, 1.0, *offset.z()],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        Matrix4 {
+            m: [
+                [*factors.x(), 0.0, 0.0, 0.0],
+                [0.0, *factors.y(), 0.0, 0.0],
+                [0.0, 0.0, *factors.z(), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // Counter-clockwise when looking down the axis towards the origin.
+    pub fn rotation(axis: Vec3, degrees: f32) -> Self {
+        let axis = axis.make_normalised();
+        let (x, y, z) = (*axis.x(), *axis.y(), *axis.z());
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Matrix4 {
+            m: [
+                [
+                    t * x * x + cos,
+                    t * x * y - sin * z,
+                    t * x * z + sin * y,
+                    0.0,
+                ],
+                [
+                    t * x * y + sin * z,
+                    t * y * y + cos,
+                    t * y * z - sin * x,
+                    0.0,
+                ],
+                [
+                    t * x * z - sin * y,
+                    t * y * z + sin * x,
+                    t * z * z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn rotation_x(degrees: f32) -> Self {
+        Self::rotation(Vec3::from(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotation_y(degrees: f32) -> Self {
+        Self::rotation(Vec3::from(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotation_z(degrees: f32) -> Self {
+        Self::rotation(Vec3::from(0.0, 0.0, 1.0), degrees)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = self.m[column][row];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, in double precision.
+    // None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        // Each row is the matrix row followed by the identity row.
+        let mut a = [[0.0f64; 8]; 4];
+        for (row, augmented) in a.iter_mut().enumerate() {
+            for (value, source) in augmented.iter_mut().zip(self.m[row].iter()) {
+                *value = f64::from(*source);
+            }
+            augmented[4 + row] = 1.0;
+        }
+
+        for column in 0..4 {
+            let pivot =
+                (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+            if a[pivot][column].abs() < 1.0e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for value in a[column].iter_mut() {
+                *value *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    let pivot_row = a[column];
+                    for (value, pivot_value) in a[row].iter_mut().zip(pivot_row.iter()) {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+
+        let mut m = [[0.0; 4]; 4];
+        for (row, augmented) in m.iter_mut().zip(a.iter()) {
+            for (value, source) in row.iter_mut().zip(augmented[4..].iter()) {
+                *value = *source as f32;
+            }
+        }
+        Some(Matrix4 { m })
+    }
+
+    pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let (x, y, z) = (*point.x(), *point.y(), *point.z());
+        Vec3::from(
+            m[0][0] * x + m[0][1] * y + m[0][2] * z + m[0][3],
+            m[1][0] * x + m[1][1] * y + m[1][2] * z + m[1][3],
+            m[2][0] * x + m[2][1] * y + m[2][2] * z + m[2][3],
+        )
+    }
+
+    // Ignores the translation.
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let (x, y, z) = (*vector.x(), *vector.y(), *vector.z());
+        Vec3::from(
+            m[0][0] * x + m[0][1] * y + m[0][2] * z,
+            m[1][0] * x + m[1][1] * y + m[1][2] * z,
+            m[2][0] * x + m[2][1] * y + m[2][2] * z,
+        )
+    }
+}
+
+impl ops::Mul<&Matrix4> for &Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: &Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * other.m[k][column]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+// The box around a transformed box: the bounds of its eight transformed
+// corners.
+pub fn transform_box(matrix: &Matrix4, bounds: &Aabb) -> Aabb {
+    let mut min = Vec3::from(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = Vec3::from(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for corner in 0..8 {
+        let pick = |axis: usize| {
+            if corner & (1 << axis) == 0 {
+                *bounds.min.get(axis)
+            } else {
+                *bounds.max.get(axis)
+            }
+        };
+        let point = matrix.transform_point(&Vec3::from(pick(0), pick(1), pick(2)));
+        min = min.min(&point);
+        max = max.max(&point);
+    }
+    Aabb::build(min, max)
+}
+
+// Places a shared object in the world. Rays are taken into object space by
+// the inverse matrix; their directions are not renormalised, so hit
+// distances carry over unchanged.
+pub struct Transformed {
+    object: Arc<dyn Hitable>,
+    to_world: Matrix4,
+    to_object: Matrix4,
+    // The inverse transpose, which keeps normals perpendicular to the
+    // surface under non-uniform scaling.
+    normal_to_world: Matrix4,
+    bounding_box: Aabb,
+}
+
+impl Transformed {
+    // None if the matrix cannot be inverted, e.g. a scale of zero.
+    pub fn new(object: Arc<dyn Hitable>, to_world: Matrix4) -> Option<Self> {
+        let to_object = to_world.inverse()?;
+        let bounds = object.bounding_box();
+        // Transforming infinite corners gives NaN, and an unbounded object
+        // stays unbounded anyway.
+        let bounding_box = if bounds.is_finite() {
+            transform_box(&to_world, &bounds)
+        } else {
+            Aabb::unbounded()
+        };
+        Some(Transformed {
+            object,
+            to_world,
+            to_object,
+            normal_to_world: to_object.transpose(),
+            bounding_box,
+        })
+    }
+}
+
+impl Hitable for Transformed {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let local_ray = Ray {
+            origin: self.to_object.transform_point(&ray.origin),
+            direction: self.to_object.transform_vector(&ray.direction),
+            time: ray.time,
+            medium_sample: ray.medium_sample,
+        };
+        let mut record = self.object.hit(&local_ray, t_min, t_max)?;
+        record.p = self.to_world.transform_point(&record.p);
+        record.normal = self
+            .normal_to_world
+            .transform_vector(&record.normal)
+            .make_normalised();
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+}
+
+// One pose of an animated instance: scale, then rotation about x, y and z
+// (in degrees), then translation, the order scene instances use.
+#[derive(Copy, Clone)]
+pub struct Keyframe {
+    pub time: f32,
+    pub scale: Vec3,
+    pub rotation_degrees: Vec3,
+    pub translation: Vec3,
+}
+
+impl Default for Keyframe {
+    fn default() -> Self {
+        Keyframe {
+            time: 0.0,
+            scale: Vec3::from(1.0, 1.0, 1.0),
+            rotation_degrees: Vec3::from(0.0, 0.0, 0.0),
+            translation: Vec3::from(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Keyframe {
+    pub fn world_matrix(&self) -> Matrix4 {
+        let degrees = &self.rotation_degrees;
+        let mut matrix = Matrix4::scaling(self.scale);
+        matrix = &Matrix4::rotation_x(*degrees.x()) * &matrix;
+        matrix = &Matrix4::rotation_y(*degrees.y()) * &matrix;
+        matrix = &Matrix4::rotation_z(*degrees.z()) * &matrix;
+        &Matrix4::translation(self.translation) * &matrix
+    }
+
+    // The inverse of `world_matrix`, undoing each step in reverse, which is much
+    // cheaper than a general inverse when posing every ray.
+    fn object_matrix(&self) -> Matrix4 {
+        let degrees = &self.rotation_degrees;
+        let mut matrix = Matrix4::translation(&self.translation * -1.0);
+        matrix = &Matrix4::rotation_z(-*degrees.z()) * &matrix;
+        matrix = &Matrix4::rotation_y(-*degrees.y()) * &matrix;
+        matrix = &Matrix4::rotation_x(-*degrees.x()) * &matrix;
+        &Matrix4::scaling(self.scale.invert_elems()) * &matrix
+    }
+
+    fn same_rotation_and_scale(&self, other: &Keyframe) -> bool {
+        same_vector(&self.scale, &other.scale)
+            && same_vector(&self.rotation_degrees, &other.rotation_degrees)
+    }
+
+    fn same_pose(&self, other: &Keyframe) -> bool {
+        self.same_rotation_and_scale(other) && same_vector(&self.translation, &other.translation)
+    }
+
+    fn matrices(&self) -> PoseMatrices {
+        PoseMatrices {
+            to_world: self.world_matrix(),
+            to_object: self.object_matrix(),
+        }
+    }
+
+    // Angles are interpolated directly, so a turn from 0 to 360 degrees
+    // spins the object once rather than leaving it in place.
+    fn lerp(&self, other: &Keyframe, s: f32) -> Keyframe {
+        let mix = |a: &Vec3, b: &Vec3| *a + &(b - a) * s;
+        Keyframe {
+            time: self.time + (other.time - self.time) * s,
+            scale: mix(&self.scale, &other.scale),
+            rotation_degrees: mix(&self.rotation_degrees, &other.rotation_degrees),
+            translation: mix(&self.translation, &other.translation),
+        }
+    }
+}
+
+fn same_vector(a: &Vec3, b: &Vec3) -> bool {
+    (0..3).all(|axis| a.get(axis) == b.get(axis))
+}
+
+#[derive(Copy, Clone)]
+struct PoseMatrices {
+    to_world: Matrix4,
+    to_object: Matrix4,
+}
+
+// Poses sampled per keyframe interval when bounding the motion.
+const MOTION_BOX_STEPS: usize = 16;
+
+// A shared object moving through a list of keyframes. Each ray sees the pose
+// at its own time, interpolated between the neighbouring keyframes and held
+// at the first and last ones outside them, so the bounding box around the
+// whole animation covers any shutter interval.
+pub struct AnimatedTransformed {
+    object: Arc<dyn Hitable>,
+    keyframes: Vec<Keyframe>,
+    // The matrices of each keyframe, used as they are wherever the object
+    // holds still: before the first keyframe, after the last, and between
+    // two keyframes with the same pose.
+    keyframe_matrices: Vec<PoseMatrices>,
+    bounding_box: Aabb,
+}
+
+impl AnimatedTransformed {
+    // None without keyframes or when one of them scales an axis by zero.
+    pub fn new(object: Arc<dyn Hitable>, mut keyframes: Vec<Keyframe>) -> Option<Self> {
+        let flattened = |key: &Keyframe| (0..3).any(|axis| *key.scale.get(axis) == 0.0);
+        if keyframes.is_empty() || keyframes.iter().any(flattened) {
+            return None;
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let bounds = object.bounding_box();
+        let mut poses = vec![keyframes[0]];
+        for pair in keyframes.windows(2) {
+            for step in 1..=MOTION_BOX_STEPS {
+                poses.push(pair[0].lerp(&pair[1], step as f32 / MOTION_BOX_STEPS as f32));
+            }
+        }
+
+        // Between two sampled poses every point of the object stays within
+        // the distance its box corners move, so growing the box around the
+        // sampled corners by the largest such move covers the curved paths
+        // that rotation produces. Where the object only translates, points
+        // move in straight lines and the box around the poses is exact.
+        let posed_corners: Vec<[Vec3; 8]> = poses
+            .iter()
+            .map(|pose| {
+                let matrix = pose.world_matrix();
+                let mut points = [Vec3::default(); 8];
+                for (corner, point) in points.iter_mut().enumerate() {
+                    let pick = |axis: usize| {
+                        if corner & (1 << axis) == 0 {
+                            *bounds.min.get(axis)
+                        } else {
+                            *bounds.max.get(axis)
+                        }
+                    };
+                    *point = matrix.transform_point(&Vec3::from(pick(0), pick(1), pick(2)));
+                }
+                points
+            })
+            .collect();
+        let mut min = posed_corners[0][0];
+        let mut max = min;
+        for point in posed_corners.iter().flatten() {
+            min = min.min(point);
+            max = max.max(point);
+        }
+        let mut largest_move = 0.0f32;
+        for (step, pair) in posed_corners.windows(2).enumerate() {
+            let interval = &keyframes[step / MOTION_BOX_STEPS..][..2];
+            if interval[0].same_rotation_and_scale(&interval[1]) {
+                continue;
+            }
+            for (from, to) in pair[0].iter().zip(pair[1].iter()) {
+                largest_move = largest_move.max((to - from).length());
+            }
+        }
+        let margin = Vec3::from(largest_move, largest_move, largest_move);
+        let bounding_box = if bounds.is_finite() {
+            Aabb::build(min - margin, max + margin)
+        } else {
+            Aabb::unbounded()
+        };
+
+        Some(AnimatedTransformed {
+            object,
+            keyframe_matrices: keyframes.iter().map(Keyframe::matrices).collect(),
+            keyframes,
+            bounding_box,
+        })
+    }
+
+    pub fn pose(&self, time: f32) -> Keyframe {
+        let next = self.keyframes.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return self.keyframes[0];
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1];
+        }
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        from.lerp(to, (time - from.time) / (to.time - from.time))
+    }
+
+    fn matrices(&self, time: f32) -> PoseMatrices {
+        let next = self.keyframes.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return self.keyframe_matrices[0];
+        }
+        if next == self.keyframes.len() || self.keyframes[next - 1].same_pose(&self.keyframes[next])
+        {
+            return self.keyframe_matrices[next - 1];
+        }
+        self.pose(time).matrices()
+    }
+}
+
+impl Hitable for AnimatedTransformed {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let PoseMatrices {
+            to_world,
+            to_object,
+        } = self.matrices(ray.time);
+        let local_ray = Ray {
+            origin: to_object.transform_point(&ray.origin),
+            direction: to_object.transform_vector(&ray.direction),
+            time: ray.time,
+            medium_sample: ray.medium_sample,
+        };
+        let mut record = self.object.hit(&local_ray, t_min, t_max)?;
+        record.p = to_world.transform_point(&record.p);
+        record.normal = to_object
+            .transpose()
+            .transform_vector(&record.normal)
+            .make_normalised();
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+}
@@ -11,6 +11,16 @@ pub union Vec3 {
     sse: __m128,
 }
 
+impl Default for Vec3 {
+    fn default() -> Self {
+        unsafe {
+            Self {
+                sse: _mm_set1_ps(0.0),
+            }
+        }
+    }
+}
+
 impl fmt::Debug for Vec3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         unsafe { write!(f, "{:?}", self.array) }
@@ -30,14 +40,6 @@ impl Vec3 {
         Self::from4(x, y, z, 0.0)
     }
 
-    pub fn default() -> Self {
-        unsafe {
-            Self {
-                sse: _mm_set1_ps(0.0),
-            }
-        }
-    }
-
     pub fn set(&mut self, other: &Vec3) {
         unsafe {
             self.sse = other.sse;
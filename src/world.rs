@@ -0,0 +1,88 @@
+// Everything a render traces rays against.
+
+use crate::bvh::{BvhSettings, BvhTree};
+use crate::hitable::Hitable;
+use crate::packet::{self, RayPacket, PACKET_SIZE};
+use crate::ray::*;
+use crate::rng::Random;
+
+// Bounded objects go into a BVH. Unbounded ones, such as infinite planes,
+// would stretch its root box over all of space, so they are kept in a list
+// and tested alongside it.
+pub struct World {
+    pub bvh: Option<BvhTree>,
+    pub unbounded: Vec<Box<dyn Hitable>>,
+}
+
+impl World {
+    // Takes every object out of `hitables`.
+    pub fn build(hitables: &mut Vec<Box<dyn Hitable>>, rnd: &mut Random) -> Self {
+        Self::build_with(hitables, rnd, &BvhSettings::default())
+    }
+
+    pub fn build_with(
+        hitables: &mut Vec<Box<dyn Hitable>>,
+        rnd: &mut Random,
+        settings: &BvhSettings,
+    ) -> Self {
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = hitables
+            .drain(..)
+            .partition(|hitable| hitable.bounding_box().is_finite());
+        World {
+            bvh: if bounded.is_empty() {
+                None
+            } else {
+                Some(BvhTree::build_with(&mut bounded, rnd, settings))
+            },
+            unbounded,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = self.bvh.as_ref().and_then(|bvh| bvh.hit(ray, t_min, t_max));
+        for hitable in &self.unbounded {
+            let t_max = closest.as_ref().map_or(t_max, |record| record.t);
+            if let Some(record) = hitable.hit(ray, t_min, t_max) {
+                closest = Some(record);
+            }
+        }
+        closest
+    }
+
+    // `hit` for the rays of a packet set in `active`.
+    pub fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: i32,
+        t_min: f32,
+        t_max: f32,
+    ) -> [Option<HitRecord<'_>>; PACKET_SIZE] {
+        let mut closest = match &self.bvh {
+            Some(bvh) => bvh.hit_packet(packet, active, t_min, &[t_max; PACKET_SIZE]),
+            None => packet::no_hits(),
+        };
+        for hitable in &self.unbounded {
+            let limits = std::array::from_fn(|lane| {
+                closest[lane]
+                    .as_ref()
+                    .map_or(t_max, |record: &HitRecord| record.t)
+            });
+            let records = hitable.hit_packet(packet, active, t_min, &limits);
+            for (lane, record) in (0..PACKET_SIZE).zip(records) {
+                if record.is_some() {
+                    closest[lane] = record;
+                }
+            }
+        }
+        closest
+    }
+}
+
+impl From<BvhTree> for World {
+    fn from(bvh: BvhTree) -> Self {
+        World {
+            bvh: Some(bvh),
+            unbounded: Vec::new(),
+        }
+    }
+}